@@ -0,0 +1,644 @@
+//! A two pass assembler for the mnemonics `disasm` prints. Besides
+//! instructions, a line may hold:
+//!
+//! * a label, `loop:`, naming the address of whatever follows it
+//! * a constant, `SPEED EQU 4`
+//! * data, `DB 0xF0, 0x90` for bytes or `DW 0x1234` for big-endian words
+//! * `INCLUDE "sprites.asm"`, read relative to the including file
+//!
+//! Operands are numbers (decimal, `0x` hex or `0b` binary), names of labels
+//! and constants, or sums and differences of those. Comments start with `;`.
+//! Mnemonics and register names are case insensitive; labels and constants
+//! are not
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Programs are loaded here, so this is the address of the first byte
+const START: u32 = 0x200;
+
+/// Deeper than this is taken to be a file including itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Why a program could not be assembled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    /// The file passed to `assemble_file` could not be read
+    Io { path: PathBuf, message: String },
+
+    /// A mistake in the source, at a 1-based line and column. `file` is None
+    /// for source passed to `assemble`
+    Source {
+        file: Option<PathBuf>,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AsmError::Io { ref path, ref message } => {
+                write!(f, "Cannot read {}: {}", path.display(), message)
+            }
+            AsmError::Source {
+                file: Some(ref file),
+                line,
+                column,
+                ref message,
+            } => write!(f, "{}:{}:{}: {}", file.display(), line, column, message),
+            AsmError::Source {
+                file: None,
+                line,
+                column,
+                ref message,
+            } => write!(f, "Line {}, column {}: {}", line, column, message),
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assemble `source` into a ROM for `Chip8::load`. Includes are read
+/// relative to the current directory
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler::new();
+    asm.parse(source, None, 0)?;
+    asm.emit()
+}
+
+/// Assemble the file at `path` into a ROM for `Chip8::load`
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AsmError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| {
+        AsmError::Io {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        }
+    })?;
+    let mut asm = Assembler::new();
+    asm.parse(&source, Some(Rc::new(path.to_path_buf())), 0)?;
+    asm.emit()
+}
+
+/// A line of some source file
+#[derive(Debug, Clone)]
+struct Loc {
+    file: Option<Rc<PathBuf>>,
+    line: usize,
+}
+
+impl Loc {
+    fn at<S: Into<String>>(&self, column: usize, message: S) -> AsmError {
+        AsmError::Source {
+            file: self.file.as_ref().map(|f| f.to_path_buf()),
+            line: self.line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+/// A mistake at a column of the line being looked at
+type Fault = (usize, String);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Num(i64),
+    Str(String),
+    Comma,
+    Colon,
+    Plus,
+    Minus,
+    LBracket,
+    RBracket,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    column: usize,
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, Fault> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        let word = |from: usize| {
+            chars[from..]
+                .iter()
+                .take_while(|c| c.is_alphanumeric() || **c == '_' || **c == '.')
+                .collect::<String>()
+        };
+        let tok = match c {
+            ';' => break,
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ',' => Tok::Comma,
+            ':' => Tok::Colon,
+            '+' => Tok::Plus,
+            '-' => Tok::Minus,
+            '[' => Tok::LBracket,
+            ']' => Tok::RBracket,
+            '"' => {
+                let text: String = chars[i + 1..].iter().take_while(|c| **c != '"').collect();
+                if i + 1 + text.chars().count() >= chars.len() {
+                    return Err((column, "Unterminated string".to_string()));
+                }
+                i += text.chars().count() + 2;
+                tokens.push(Token {
+                    tok: Tok::Str(text),
+                    column,
+                });
+                continue;
+            }
+            _ if c.is_ascii_digit() => {
+                let text = word(i);
+                i += text.chars().count();
+                tokens.push(Token {
+                    tok: Tok::Num(parse_number(&text).ok_or_else(|| {
+                        (column, format!("Invalid number '{}'", text))
+                    })?),
+                    column,
+                });
+                continue;
+            }
+            _ if c.is_alphabetic() || c == '_' || c == '.' => {
+                let text = word(i);
+                i += text.chars().count();
+                tokens.push(Token {
+                    tok: Tok::Ident(text),
+                    column,
+                });
+                continue;
+            }
+            _ => return Err((column, format!("Unexpected character '{}'", c))),
+        };
+        tokens.push(Token { tok, column });
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Num(i64),
+    Name(String),
+}
+
+/// Terms to add up, each with whether it is subtracted and its column
+#[derive(Debug, Clone)]
+struct Expr {
+    terms: Vec<(bool, Term, usize)>,
+}
+
+/// Parse all of `tokens` as an expression. `column` is reported if there
+/// are none
+fn parse_expr(tokens: &[Token], column: usize) -> Result<Expr, Fault> {
+    let mut terms = Vec::new();
+    let mut negative = false;
+    let mut want_term = true;
+    for t in tokens {
+        match (&t.tok, want_term) {
+            (&Tok::Minus, true) if terms.is_empty() && !negative => {
+                negative = true;
+                continue;
+            }
+            (&Tok::Num(n), true) => terms.push((negative, Term::Num(n), t.column)),
+            (Tok::Ident(name), true) => {
+                terms.push((negative, Term::Name(name.clone()), t.column))
+            }
+            (&Tok::Plus, false) => negative = false,
+            (&Tok::Minus, false) => negative = true,
+            _ => return Err((t.column, "Expected a number or a name".to_string())),
+        }
+        want_term = !want_term;
+    }
+    if want_term {
+        let column = tokens.last().map_or(column, |t| t.column);
+        return Err((column, "Expected a number or a name".to_string()));
+    }
+    Ok(Expr { terms })
+}
+
+/// Split operands at commas, each with the column it starts at
+fn split_operands(tokens: &[Token]) -> Result<Vec<(&[Token], usize)>, Fault> {
+    let mut operands = Vec::new();
+    let mut rest = tokens;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|t| t.tok == Tok::Comma).unwrap_or(rest.len());
+        if end == 0 || end + 1 == rest.len() {
+            return Err((rest[end.min(rest.len() - 1)].column, "Missing operand".to_string()));
+        }
+        operands.push((&rest[..end], rest[0].column));
+        rest = &rest[(end + 1).min(rest.len())..];
+    }
+    Ok(operands)
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    V(u16),
+    I,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    IndirectI,
+    Long(Expr),
+    Expr(Expr),
+}
+
+fn parse_operand(tokens: &[Token], column: usize) -> Result<Operand, Fault> {
+    if let [Token { tok: Tok::Ident(ref name), .. }] = *tokens {
+        let upper = name.to_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Some(Operand::I),
+            "DT" => Some(Operand::Dt),
+            "ST" => Some(Operand::St),
+            "K" => Some(Operand::K),
+            "F" => Some(Operand::F),
+            "HF" => Some(Operand::Hf),
+            "B" => Some(Operand::B),
+            "R" => Some(Operand::R),
+            _ if upper.len() == 2 && upper.starts_with('V') => {
+                u16::from_str_radix(&upper[1..], 16).ok().map(Operand::V)
+            }
+            _ => None,
+        };
+        if let Some(operand) = operand {
+            return Ok(operand);
+        }
+    }
+    let toks: Vec<&Tok> = tokens.iter().map(|t| &t.tok).collect();
+    match toks.as_slice() {
+        [Tok::LBracket, Tok::Ident(i), Tok::RBracket] if i.eq_ignore_ascii_case("I") => {
+            Ok(Operand::IndirectI)
+        }
+        [Tok::Ident(long), ..] if long.eq_ignore_ascii_case("LONG") => {
+            Ok(Operand::Long(parse_expr(&tokens[1..], tokens[0].column)?))
+        }
+        _ => Ok(Operand::Expr(parse_expr(tokens, column)?)),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    /// Upper case mnemonic and its column, then the operands and theirs
+    Op(String, usize, Vec<(Operand, usize)>),
+    Db(Vec<(Expr, usize)>),
+    Dw(Vec<(Expr, usize)>),
+}
+
+#[derive(Debug, Clone)]
+enum Symbol {
+    Label(u32),
+    Const(Expr, Loc),
+}
+
+/// An operand with every expression evaluated
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Arg {
+    V(u16),
+    I,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    IndirectI,
+    Long(i64),
+    Num(i64),
+}
+
+struct Assembler {
+    stmts: Vec<(Loc, Stmt)>,
+    symbols: HashMap<String, Symbol>,
+    size: u32,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Assembler {
+            stmts: Vec::new(),
+            symbols: HashMap::new(),
+            size: 0,
+        }
+    }
+
+    /// First pass: tokenize, define labels and constants, and lay out memory
+    fn parse(
+        &mut self,
+        source: &str,
+        file: Option<Rc<PathBuf>>,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        for (n, text) in source.lines().enumerate() {
+            let loc = Loc {
+                file: file.clone(),
+                line: n + 1,
+            };
+            self.parse_line(text, &loc, depth)?;
+            if START + self.size > 0x10000 {
+                return Err(loc.at(1, "Program does not fit in memory"));
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_line(&mut self, text: &str, loc: &Loc, depth: usize) -> Result<(), AsmError> {
+        let tokens = tokenize(text).map_err(|(c, m)| loc.at(c, m))?;
+        let mut rest = &tokens[..];
+
+        if let [Token { tok: Tok::Ident(ref name), column }, ref colon, ..] = *rest {
+            if colon.tok == Tok::Colon {
+                self.define(name, column, Symbol::Label(START + self.size), loc)?;
+                rest = &rest[2..];
+            }
+        }
+        let (word, column) = match rest.first() {
+            None => return Ok(()),
+            Some(&Token { tok: Tok::Ident(ref word), column }) => (word.clone(), column),
+            Some(t) => return Err(loc.at(t.column, "Expected an instruction")),
+        };
+        let upper = word.to_uppercase();
+        let after = column + word.chars().count();
+
+        if let Some(&Token { tok: Tok::Ident(ref equ), column: equ_column }) = rest.get(1) {
+            if equ.eq_ignore_ascii_case("EQU") {
+                let expr = parse_expr(&rest[2..], equ_column + 3).map_err(|(c, m)| loc.at(c, m))?;
+                return self.define(&word, column, Symbol::Const(expr, loc.clone()), loc);
+            }
+        }
+
+        let operands = split_operands(&rest[1..]).map_err(|(c, m)| loc.at(c, m))?;
+        let stmt = match upper.as_str() {
+            "DB" | "DW" => {
+                let mut exprs = Vec::new();
+                for (tokens, c) in operands {
+                    exprs.push((parse_expr(tokens, c).map_err(|(c, m)| loc.at(c, m))?, c));
+                }
+                if exprs.is_empty() {
+                    return Err(loc.at(after, "Missing operand"));
+                }
+                if upper == "DB" {
+                    self.size += exprs.len() as u32;
+                    Stmt::Db(exprs)
+                } else {
+                    self.size += 2 * exprs.len() as u32;
+                    Stmt::Dw(exprs)
+                }
+            }
+            "INCLUDE" => {
+                let path = match *operands.as_slice() {
+                    [([Token { tok: Tok::Str(ref path), .. }], _)] => path.clone(),
+                    _ => return Err(loc.at(after, "Expected a quoted file name")),
+                };
+                return self.include(&path, column, loc, depth);
+            }
+            _ => {
+                let mut ops = Vec::new();
+                for (tokens, c) in operands {
+                    ops.push((parse_operand(tokens, c).map_err(|(c, m)| loc.at(c, m))?, c));
+                }
+                let long = ops.iter().any(|(o, _)| matches!(*o, Operand::Long(_)));
+                self.size += if long { 4 } else { 2 };
+                Stmt::Op(upper, column, ops)
+            }
+        };
+        self.stmts.push((loc.clone(), stmt));
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        path: &str,
+        column: usize,
+        loc: &Loc,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(loc.at(column, "Includes are nested too deeply"));
+        }
+        let dir = loc.file
+            .as_ref()
+            .and_then(|f| f.parent())
+            .map_or_else(PathBuf::new, |d| d.to_path_buf());
+        let full = dir.join(path);
+        let source = fs::read_to_string(&full)
+            .map_err(|e| loc.at(column, format!("Cannot read {}: {}", full.display(), e)))?;
+        self.parse(&source, Some(Rc::new(full)), depth + 1)
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        column: usize,
+        symbol: Symbol,
+        loc: &Loc,
+    ) -> Result<(), AsmError> {
+        if self.symbols.contains_key(name) {
+            return Err(loc.at(column, format!("'{}' is already defined", name)));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    /// Second pass: evaluate operands and encode everything
+    fn emit(&self) -> Result<Vec<u8>, AsmError> {
+        let mut out = Vec::with_capacity(self.size as usize);
+        for (loc, stmt) in &self.stmts {
+            match *stmt {
+                Stmt::Db(ref exprs) => {
+                    for &(ref e, c) in exprs {
+                        let v = self.eval(e, loc, 0)?;
+                        out.push(byte(v, c).map_err(|(c, m)| loc.at(c, m))? as u8);
+                    }
+                }
+                Stmt::Dw(ref exprs) => {
+                    for &(ref e, c) in exprs {
+                        let v = self.eval(e, loc, 0)?;
+                        let w = word(v, c).map_err(|(c, m)| loc.at(c, m))?;
+                        out.extend_from_slice(&[(w >> 8) as u8, w as u8]);
+                    }
+                }
+                Stmt::Op(ref mnemonic, column, ref ops) => {
+                    let mut args = Vec::with_capacity(ops.len());
+                    for &(ref op, c) in ops {
+                        let arg = match *op {
+                            Operand::V(x) => Arg::V(x),
+                            Operand::I => Arg::I,
+                            Operand::Dt => Arg::Dt,
+                            Operand::St => Arg::St,
+                            Operand::K => Arg::K,
+                            Operand::F => Arg::F,
+                            Operand::Hf => Arg::Hf,
+                            Operand::B => Arg::B,
+                            Operand::R => Arg::R,
+                            Operand::IndirectI => Arg::IndirectI,
+                            Operand::Long(ref e) => Arg::Long(self.eval(e, loc, 0)?),
+                            Operand::Expr(ref e) => Arg::Num(self.eval(e, loc, 0)?),
+                        };
+                        args.push((arg, c));
+                    }
+                    for w in encode(mnemonic, column, &args).map_err(|(c, m)| loc.at(c, m))? {
+                        out.extend_from_slice(&[(w >> 8) as u8, w as u8]);
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn eval(&self, expr: &Expr, loc: &Loc, depth: usize) -> Result<i64, AsmError> {
+        let mut total: i64 = 0;
+        for &(negative, ref term, column) in &expr.terms {
+            let v = match *term {
+                Term::Num(n) => n,
+                Term::Name(ref name) => {
+                    match self.symbols.get(name) {
+                        Some(&Symbol::Label(address)) => address as i64,
+                        Some(Symbol::Const(e, at)) => {
+                            if depth > self.symbols.len() {
+                                let message = format!("'{}' is defined in terms of itself", name);
+                                return Err(loc.at(column, message));
+                            }
+                            self.eval(e, at, depth + 1)?
+                        }
+                        None => return Err(loc.at(column, format!("Undefined name '{}'", name))),
+                    }
+                }
+            };
+            total = if negative { total.wrapping_sub(v) } else { total.wrapping_add(v) };
+        }
+        Ok(total)
+    }
+}
+
+fn address(v: i64, column: usize) -> Result<u16, Fault> {
+    if !(0..=0xFFF).contains(&v) {
+        return Err((column, format!("Address {:#X} does not fit in 12 bits", v)));
+    }
+    Ok(v as u16)
+}
+
+/// Negative bytes are taken as two's complement
+fn byte(v: i64, column: usize) -> Result<u16, Fault> {
+    if !(-0x80..=0xFF).contains(&v) {
+        return Err((column, format!("Value {} does not fit in a byte", v)));
+    }
+    Ok(v as u8 as u16)
+}
+
+fn word(v: i64, column: usize) -> Result<u16, Fault> {
+    if !(-0x8000..=0xFFFF).contains(&v) {
+        return Err((column, format!("Value {} does not fit in a word", v)));
+    }
+    Ok(v as u16)
+}
+
+fn nibble(v: i64, column: usize) -> Result<u16, Fault> {
+    if !(0..=0xF).contains(&v) {
+        return Err((column, format!("Value {} does not fit in a nibble", v)));
+    }
+    Ok(v as u16)
+}
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "AUDIO", "SYS", "SCD", "SCU", "JP", "CALL",
+    "SE", "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL",
+    "RND", "DRW", "SKP", "SKNP", "PLANE", "PITCH",
+];
+
+/// The one or two words `mnemonic` with `args` assembles to
+fn encode(mnemonic: &str, column: usize, args: &[(Arg, usize)]) -> Result<Vec<u16>, Fault> {
+    use self::Arg::*;
+
+    let word = match (mnemonic, args) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("AUDIO", []) => 0xF002,
+        ("SYS", &[(Num(a), c)]) => address(a, c)?,
+        ("SCD", &[(Num(n), c)]) => 0x00C0 | nibble(n, c)?,
+        ("SCU", &[(Num(n), c)]) => 0x00D0 | nibble(n, c)?,
+        ("JP", &[(Num(a), c)]) => 0x1000 | address(a, c)?,
+        ("JP", &[(V(0), _), (Num(a), c)]) => 0xB000 | address(a, c)?,
+        ("CALL", &[(Num(a), c)]) => 0x2000 | address(a, c)?,
+        ("SE", &[(V(x), _), (Num(n), c)]) => 0x3000 | x << 8 | byte(n, c)?,
+        ("SE", &[(V(x), _), (V(y), _)]) => 0x5000 | x << 8 | y << 4,
+        ("SNE", &[(V(x), _), (Num(n), c)]) => 0x4000 | x << 8 | byte(n, c)?,
+        ("SNE", &[(V(x), _), (V(y), _)]) => 0x9000 | x << 8 | y << 4,
+        ("SAVE", &[(V(x), _), (V(y), _)]) => 0x5002 | x << 8 | y << 4,
+        ("LOAD", &[(V(x), _), (V(y), _)]) => 0x5003 | x << 8 | y << 4,
+        ("LD", &[(V(x), _), (Num(n), c)]) => 0x6000 | x << 8 | byte(n, c)?,
+        ("LD", &[(V(x), _), (V(y), _)]) => 0x8000 | x << 8 | y << 4,
+        ("LD", &[(I, _), (Num(a), c)]) => 0xA000 | address(a, c)?,
+        ("LD", &[(I, _), (Long(a), c)]) => return Ok(vec![0xF000, self::word(a, c)?]),
+        ("LD", &[(V(x), _), (Dt, _)]) => 0xF007 | x << 8,
+        ("LD", &[(V(x), _), (K, _)]) => 0xF00A | x << 8,
+        ("LD", &[(Dt, _), (V(x), _)]) => 0xF015 | x << 8,
+        ("LD", &[(St, _), (V(x), _)]) => 0xF018 | x << 8,
+        ("LD", &[(F, _), (V(x), _)]) => 0xF029 | x << 8,
+        ("LD", &[(Hf, _), (V(x), _)]) => 0xF030 | x << 8,
+        ("LD", &[(B, _), (V(x), _)]) => 0xF033 | x << 8,
+        ("LD", &[(IndirectI, _), (V(x), _)]) => 0xF055 | x << 8,
+        ("LD", &[(V(x), _), (IndirectI, _)]) => 0xF065 | x << 8,
+        ("LD", &[(R, _), (V(x), _)]) => 0xF075 | x << 8,
+        ("LD", &[(V(x), _), (R, _)]) => 0xF085 | x << 8,
+        ("ADD", &[(V(x), _), (Num(n), c)]) => 0x7000 | x << 8 | byte(n, c)?,
+        ("ADD", &[(V(x), _), (V(y), _)]) => 0x8004 | x << 8 | y << 4,
+        ("ADD", &[(I, _), (V(x), _)]) => 0xF01E | x << 8,
+        ("OR", &[(V(x), _), (V(y), _)]) => 0x8001 | x << 8 | y << 4,
+        ("AND", &[(V(x), _), (V(y), _)]) => 0x8002 | x << 8 | y << 4,
+        ("XOR", &[(V(x), _), (V(y), _)]) => 0x8003 | x << 8 | y << 4,
+        ("SUB", &[(V(x), _), (V(y), _)]) => 0x8005 | x << 8 | y << 4,
+        ("SHR", &[(V(x), _), (V(y), _)]) => 0x8006 | x << 8 | y << 4,
+        ("SHR", &[(V(x), _)]) => 0x8006 | x << 8 | x << 4,
+        ("SUBN", &[(V(x), _), (V(y), _)]) => 0x8007 | x << 8 | y << 4,
+        ("SHL", &[(V(x), _), (V(y), _)]) => 0x800E | x << 8 | y << 4,
+        ("SHL", &[(V(x), _)]) => 0x800E | x << 8 | x << 4,
+        ("RND", &[(V(x), _), (Num(n), c)]) => 0xC000 | x << 8 | byte(n, c)?,
+        ("DRW", &[(V(x), _), (V(y), _), (Num(n), c)]) => 0xD000 | x << 8 | y << 4 | nibble(n, c)?,
+        ("SKP", &[(V(x), _)]) => 0xE09E | x << 8,
+        ("SKNP", &[(V(x), _)]) => 0xE0A1 | x << 8,
+        ("PLANE", &[(Num(n), c)]) => 0xF001 | nibble(n, c)? << 8,
+        ("PITCH", &[(V(x), _)]) => 0xF03A | x << 8,
+        _ if MNEMONICS.contains(&mnemonic) => {
+            return Err((column, format!("Invalid operands for {}", mnemonic)))
+        }
+        _ => return Err((column, format!("Unknown instruction '{}'", mnemonic))),
+    };
+    Ok(vec![word])
+}
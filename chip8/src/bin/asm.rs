@@ -0,0 +1,51 @@
+//! `asm <source> [-o <output>]` assembles a program into a `.ch8` ROM,
+//! written next to the source unless `-o` is given. `asm -d <rom>` prints a
+//! disassembly of a ROM that assembles back into it
+
+extern crate chip8;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process;
+
+use chip8::{asm, disasm};
+
+const USAGE: &str = "usage: asm <source> [-o <output>]\n       asm -d <rom>";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn disassemble(path: &str) {
+    let mut rom = Vec::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut rom)) {
+        fail(&format!("Cannot read {}: {}", path, e));
+    }
+    for line in disasm::disassemble(&rom) {
+        let hex: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        println!("    {:<24}; {:04X}  {}", line.text(), line.address, hex.join(""));
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (source, output) = match args.len() {
+        1 if !args[0].starts_with('-') => {
+            (args[0].clone(), PathBuf::from(&args[0]).with_extension("ch8"))
+        }
+        2 if args[0] == "-d" => return disassemble(&args[1]),
+        3 if args[1] == "-o" => (args[0].clone(), PathBuf::from(&args[2])),
+        _ => fail(USAGE),
+    };
+
+    let rom = match asm::assemble_file(&source) {
+        Ok(rom) => rom,
+        Err(e) => fail(&e.to_string()),
+    };
+    if let Err(e) = File::create(&output).and_then(|mut f| f.write_all(&rom)) {
+        fail(&format!("Cannot write {}: {}", output.display(), e));
+    }
+}
@@ -0,0 +1,188 @@
+//! `headless <rom>` runs a ROM without a window, sound or keyboard, for N
+//! frames or until it halts, then prints the screen as ASCII art. It can
+//! also save the screen as a PBM or PNG, record it as a GIF or a PNG per
+//! frame, record the sound as a WAV, and press keys on a schedule.
+//! Without a frame count it also stops at a key wait that no scripted
+//! press is left to answer
+//!
+//! ```text
+//! headless pong.ch8 --frames 600 --press 120:1:30 --png pong.png
+//! ```
+
+extern crate chip8;
+
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::process;
+use std::str::FromStr;
+
+use chip8::headless::{KeyPress, ScriptedInput};
+use chip8::record::Recorder;
+use chip8::sound::{Tone, WavBeeper};
+use chip8::{image, Chip8, Quirks};
+
+const USAGE: &str = "usage: headless <rom> [--frames <n>] [--ipf <n>] [--quirks <preset>] \
+                     [--seed <n>]\n                \
+                     [--press <frame>:<key>[:<frames>]]... [--pbm <file>] [--png <file>] \
+                     [--scale <n>]\n                [--palette <name>] [--wav <file>] \
+                     [--record <file.gif | directory>]";
+
+/// Sample rate of `--wav` recordings
+const WAV_RATE: u32 = 44100;
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+/// Parse the value following option `name`, or exit with a message
+fn value<T: FromStr>(name: &str, value: Option<String>) -> T
+where
+    T::Err: ToString,
+{
+    match value {
+        Some(v) => v.parse().unwrap_or_else(|e: T::Err| fail(&e.to_string())),
+        None => fail(&format!("{} needs a value\n{}", name, USAGE)),
+    }
+}
+
+struct Options {
+    rom: String,
+    frames: Option<u64>,
+    ipf: usize,
+    quirks: Quirks,
+    seed: Option<u64>,
+    presses: Vec<KeyPress>,
+    pbm: Option<String>,
+    png: Option<String>,
+    scale: usize,
+    palette: [(u8, u8, u8); 16],
+    wav: Option<String>,
+    record: Option<String>,
+}
+
+fn options() -> Options {
+    let mut opt = Options {
+        rom: String::new(),
+        frames: None,
+        ipf: chip8::DEFAULT_IPF,
+        quirks: Quirks::default(),
+        seed: None,
+        presses: Vec::new(),
+        pbm: None,
+        png: None,
+        scale: 1,
+        palette: image::PALETTE,
+        wav: None,
+        record: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => opt.frames = Some(value(&arg, args.next())),
+            "--ipf" => opt.ipf = value(&arg, args.next()),
+            "--quirks" => opt.quirks = value(&arg, args.next()),
+            "--seed" => opt.seed = Some(value(&arg, args.next())),
+            "--press" => opt.presses.push(value(&arg, args.next())),
+            "--pbm" => opt.pbm = Some(value(&arg, args.next())),
+            "--png" => opt.png = Some(value(&arg, args.next())),
+            "--scale" => opt.scale = value(&arg, args.next()),
+            "--palette" => {
+                let name: String = value(&arg, args.next());
+                opt.palette = image::palette(&name).unwrap_or_else(|| {
+                    let names = image::PALETTE_NAMES.join(", ");
+                    fail(&format!("Unknown palette '{}', expected one of {}", name, names))
+                });
+            }
+            "--wav" => opt.wav = Some(value(&arg, args.next())),
+            "--record" => opt.record = Some(value(&arg, args.next())),
+            _ if arg.starts_with('-') || !opt.rom.is_empty() => fail(USAGE),
+            _ => opt.rom = arg,
+        }
+    }
+    if opt.rom.is_empty() || opt.scale == 0 {
+        fail(USAGE);
+    }
+    opt
+}
+
+fn save(path: &str, data: &[u8]) {
+    if let Err(e) = File::create(path).and_then(|mut f| f.write_all(data)) {
+        fail(&format!("Cannot write {}: {}", path, e));
+    }
+}
+
+fn main() {
+    let opt = options();
+    let mut rom = Vec::new();
+    if let Err(e) = File::open(&opt.rom).and_then(|mut f| f.read_to_end(&mut rom)) {
+        fail(&format!("Cannot read {}: {}", opt.rom, e));
+    }
+
+    let wav = opt.wav.as_ref().map(|path| {
+        File::create(path)
+            .and_then(|f| WavBeeper::new(BufWriter::new(f), Tone::default(), WAV_RATE, ()))
+            .unwrap_or_else(|e| fail(&format!("Cannot write {}: {}", path, e)))
+    });
+    let mut recorder = opt.record.as_ref().map(|path| {
+        Recorder::create(path, &opt.palette, opt.scale)
+            .unwrap_or_else(|e| fail(&format!("Cannot record to {}: {}", path, e)))
+    });
+    let input = ScriptedInput::new(opt.presses);
+    let mut c = Chip8::new((), input, wav, opt.quirks);
+    if let Some(seed) = opt.seed {
+        c.seed_rng(seed);
+    }
+    if let Err(e) = c.load(rom) {
+        fail(&e.to_string());
+    }
+
+    let mut frame = 0;
+    let mut error = None;
+    while opt.frames.is_none_or(|n| frame < n) {
+        c.input_mut().set_frame(frame);
+        match c.run_frame(opt.ipf) {
+            Ok(result) if result.halted => break,
+            Ok(result) => {
+                frame += 1;
+                if let Some(ref mut recorder) = recorder {
+                    if let Err(e) = recorder.frame(c.framebuffer()) {
+                        fail(&format!("Cannot record frame {}: {}", frame, e));
+                    }
+                }
+                let stuck = result.waiting_for_key && c.input_mut().is_finished();
+                if stuck && opt.frames.is_none() {
+                    break;
+                }
+            }
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+    }
+
+    // The screen is saved even if the program failed, to show how far it got
+    let grid = c.framebuffer();
+    print!("{}", image::ascii(grid));
+    if let Some(ref path) = opt.pbm {
+        save(path, &image::pbm(grid, opt.scale));
+    }
+    if let Some(ref path) = opt.png {
+        save(path, &image::png(grid, &opt.palette, opt.scale));
+    }
+    if let Some(recorder) = recorder {
+        if let Err(e) = recorder.finish() {
+            fail(&format!("Cannot record to {}: {}", opt.record.unwrap(), e));
+        }
+    }
+    if let Some(ref mut wav) = *c.beeper_mut() {
+        if let Err(e) = wav.finish() {
+            fail(&format!("Cannot write {}: {}", opt.wav.unwrap(), e));
+        }
+    }
+    if let Some(e) = error {
+        fail(&format!("Frame {}: {}", frame, e));
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeSet;
+
+use super::{Access, Beeper, Chip8, Chip8Error, Display, Input};
+use disasm::{decode, Instruction};
+
+/// A register whose changes can be watched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    /// V0 through VF
+    V(usize),
+    /// The address register
+    I,
+}
+
+/// Stops execution when the watched thing is touched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Watchpoint {
+    /// An instruction reads the byte at this address
+    Read(u16),
+    /// An instruction writes the byte at this address
+    Write(u16),
+    /// An instruction changes the value of this register
+    Register(Register),
+}
+
+/// Why the debugger paused execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The instruction at this address is next, and has a breakpoint on it
+    Breakpoint(u16),
+
+    /// The instruction at `pc` triggered `watchpoint`
+    Watchpoint { watchpoint: Watchpoint, pc: u16 },
+
+    /// A step, step over or run until return has completed
+    Step,
+
+    /// The program executed `EXIT`
+    Halted,
+}
+
+/// Where execution is headed when the debugger is not paused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Goal {
+    Pause,
+    Continue,
+    /// For one instruction
+    Step,
+    /// Until the call at `from` returns to the instruction after it
+    StepOver { from: u16, depth: usize },
+    /// Until the stack is shallower than `depth`
+    Return { depth: usize },
+}
+
+/// Breakpoints, watchpoints and stepping on top of a `Chip8`.
+///
+/// The debugger does not own the machine; hosts hand it over on each call.
+/// Once per frame they call `run` in place of `cycle`, which executes
+/// instructions until the budget for the frame is spent or something makes
+/// it pause. `resume`, `step_over` and `run_until_return` then set it going
+/// again. A new debugger is running, so attaching one changes nothing until
+/// a breakpoint or watchpoint is added
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<Watchpoint>,
+    goal: Goal,
+
+    /// The breakpoint execution last paused at, which is not hit again when
+    /// execution resumes from it
+    resumed_from: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            goal: Goal::Continue,
+            resumed_from: None,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    pub fn breakpoints(&self) -> Vec<u16> {
+        self.breakpoints.iter().cloned().collect()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.insert(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.remove(&watchpoint);
+    }
+
+    pub fn watchpoints(&self) -> Vec<Watchpoint> {
+        self.watchpoints.iter().cloned().collect()
+    }
+
+    /// True unless paused
+    pub fn is_running(&self) -> bool {
+        self.goal != Goal::Pause
+    }
+
+    /// Stop before the next instruction
+    pub fn pause(&mut self) {
+        self.goal = Goal::Pause;
+    }
+
+    /// Run until a breakpoint or watchpoint
+    pub fn resume(&mut self) {
+        self.goal = Goal::Continue;
+    }
+
+    /// Execute one instruction, entering calls, then pause. Unlike
+    /// `step_into` this takes effect on the next `run`, which may first wait
+    /// for a timer tick
+    pub fn step(&mut self) {
+        self.goal = Goal::Step;
+    }
+
+    /// Execute the next instruction; a call is run through to its return
+    pub fn step_over<D: Display, I: Input, B: Beeper>(&mut self, c: &Chip8<D, I, B>) {
+        self.goal = match decode(c.next_opcode()) {
+            Instruction::Call(_) => {
+                Goal::StepOver {
+                    from: c.pc,
+                    depth: c.stack.len(),
+                }
+            }
+            _ => Goal::Step,
+        };
+    }
+
+    /// Run until the current subroutine returns. Outside of any subroutine
+    /// this is the same as `resume`
+    pub fn run_until_return<D: Display, I: Input, B: Beeper>(&mut self, c: &Chip8<D, I, B>) {
+        self.goal = match c.stack.len() {
+            0 => Goal::Continue,
+            depth => Goal::Return { depth },
+        };
+    }
+
+    /// Execute exactly one instruction, entering calls, and pause. Returns
+    /// None if the CPU is waiting for the next timer tick and did nothing
+    pub fn step_into<D: Display, I: Input, B: Beeper>(
+        &mut self,
+        c: &mut Chip8<D, I, B>,
+    ) -> Result<Option<Stop>, Chip8Error> {
+        if c.vblank_wait {
+            return Ok(None);
+        }
+        self.goal = Goal::Pause;
+        self.resumed_from = None;
+        Ok(Some(self.execute(c)?.unwrap_or(Stop::Step)))
+    }
+
+    /// Execute up to `cycles` instructions towards the current goal. Returns
+    /// the reason if execution paused; once paused this does nothing until
+    /// execution is resumed. Also returns early, with None, if the CPU waits
+    /// for the next timer tick
+    pub fn run<D: Display, I: Input, B: Beeper>(
+        &mut self,
+        c: &mut Chip8<D, I, B>,
+        cycles: usize,
+    ) -> Result<Option<Stop>, Chip8Error> {
+        for _ in 0..cycles {
+            if self.goal == Goal::Pause || c.vblank_wait {
+                break;
+            }
+            if self.breakpoints.contains(&c.pc) && self.resumed_from != Some(c.pc) {
+                self.goal = Goal::Pause;
+                self.resumed_from = Some(c.pc);
+                return Ok(Some(Stop::Breakpoint(c.pc)));
+            }
+            self.resumed_from = None;
+
+            let stop = match self.execute(c) {
+                Ok(stop) => stop,
+                Err(e) => {
+                    self.goal = Goal::Pause;
+                    return Err(e);
+                }
+            };
+            let reached = match self.goal {
+                Goal::Step => true,
+                Goal::StepOver { from, depth } => {
+                    c.pc == from.wrapping_add(2) && c.stack.len() == depth
+                }
+                Goal::Return { depth } => c.stack.len() < depth,
+                Goal::Pause | Goal::Continue => false,
+            };
+            if let Some(stop) = stop.or(if reached { Some(Stop::Step) } else { None }) {
+                self.goal = Goal::Pause;
+                return Ok(Some(stop));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Run one cycle, reporting any watchpoint it triggered or a halt
+    fn execute<D: Display, I: Input, B: Beeper>(
+        &mut self,
+        c: &mut Chip8<D, I, B>,
+    ) -> Result<Option<Stop>, Chip8Error> {
+        let pc = c.pc;
+        let register = c.register;
+        let address_reg = c.address_reg;
+        let watch_memory = self.watchpoints.iter().any(|w| match *w {
+            Watchpoint::Read(_) | Watchpoint::Write(_) => true,
+            Watchpoint::Register(_) => false,
+        });
+        c.accesses = if watch_memory { Some(Vec::new()) } else { None };
+
+        let running = c.cycle();
+        let accesses = c.accesses.take().unwrap_or_default();
+        if !running? {
+            return Ok(Some(Stop::Halted));
+        }
+
+        let hit = self.watchpoints.iter().find(|w| match **w {
+            Watchpoint::Read(a) => accesses.contains(&Access::Read(a as usize)),
+            Watchpoint::Write(a) => accesses.contains(&Access::Write(a as usize)),
+            Watchpoint::Register(Register::V(x)) => register.get(x) != c.register.get(x),
+            Watchpoint::Register(Register::I) => address_reg != c.address_reg,
+        });
+        Ok(hit.map(|w| {
+            Stop::Watchpoint {
+                watchpoint: *w,
+                pc,
+            }
+        }))
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
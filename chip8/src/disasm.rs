@@ -0,0 +1,370 @@
+//! Decoding of CHIP-8 opcodes, and their rendering as text in the style of
+//! Cowgod's technical reference: `LD V1, 0x20`, `DRW V0, V1, 5`, and so on.
+//! The SUPER-CHIP and XO-CHIP additions use the usual names for them (`SCD`,
+//! `HIGH`, `PLANE`, ...)
+
+use std::fmt;
+
+mod data {
+    pub type Unknown = u16;
+    pub type Address = u16;
+    pub type Register = usize;
+    pub type Value = u8;
+    pub type Registers = (usize, usize);
+    pub type RegisterAndValue = (usize, u8);
+    pub type RegistersAndValue = (usize, usize, u8);
+}
+
+/// One decoded instruction, with its operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// invalid opcode
+    Nope(data::Unknown),
+
+    /// 0XXX
+    Sys(data::Address),
+    Clr,
+    Ret,
+    ScrollDown(data::Value),
+    ScrollUp(data::Value),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LoRes,
+    HiRes,
+
+    /// 1XXX
+    GoTo(data::Address),
+
+    /// 2XXX
+    Call(data::Address),
+
+    /// 3XXX
+    SkipEq(data::RegisterAndValue),
+
+    /// 4XXX
+    SkipNeq(data::RegisterAndValue),
+
+    /// 5XXX
+    SkipEqR(data::Registers),
+    SaveRange(data::Registers),
+    LoadRange(data::Registers),
+
+    /// 6XXX
+    SetR(data::RegisterAndValue),
+
+    /// 7XXX
+    AddR(data::RegisterAndValue),
+
+    /// 8XXX
+    AssignR(data::Registers),
+    OrR(data::Registers),
+    AndR(data::Registers),
+    XorR(data::Registers),
+    AddR2(data::Registers),
+    SubR(data::Registers),
+    RShiftR(data::Registers),
+    SubR2(data::Registers),
+    LShiftR(data::Registers),
+
+    /// 9XXX
+    SkipNeqR(data::Registers),
+
+    /// AXXX
+    Mem(data::Address),
+
+    /// BXXX
+    Jmp(data::Address),
+
+    /// CXXX
+    Rand(data::RegisterAndValue),
+
+    /// DXXX
+    Disp(data::RegistersAndValue),
+
+    /// EXXX
+    KeyPress(data::Register),
+    KeyNoPress(data::Register),
+
+    /// FXXX
+    /// `F000`, whose address is the following word
+    LongMem,
+    Plane(data::Value),
+    Audio,
+    Pitch(data::Register),
+    GetDelay(data::Register),
+    WaitKey(data::Register),
+    SetDelay(data::Register),
+    SetSound(data::Register),
+    AddM(data::Register),
+    Sprite(data::Register),
+    BigSprite(data::Register),
+    Bcd(data::Register),
+    DumpR(data::Register),
+    LoadR(data::Register),
+    SaveFlags(data::Register),
+    LoadFlags(data::Register),
+}
+
+/// Decode a two byte opcode. Anything that is not a CHIP-8, SUPER-CHIP or
+/// XO-CHIP instruction decodes to `Instruction::Nope`
+pub fn decode(code: u16) -> Instruction {
+    let address: u16 = opcode_addr(code);
+    let nibble4: u8 = opcode_nibble4(code);
+    let byte2: u8 = opcode_byte2(code);
+
+    match opcode_n1(code) {
+        0 => {
+            match address {
+                0x0E0 => Instruction::Clr,
+                0x0EE => Instruction::Ret,
+                0x0FB => Instruction::ScrollRight,
+                0x0FC => Instruction::ScrollLeft,
+                0x0FD => Instruction::Exit,
+                0x0FE => Instruction::LoRes,
+                0x0FF => Instruction::HiRes,
+                _ if address & 0xFF0 == 0x0C0 => Instruction::ScrollDown(nibble4),
+                _ if address & 0xFF0 == 0x0D0 => Instruction::ScrollUp(nibble4),
+                _ => Instruction::Sys(address),
+            }
+        }
+        1 => Instruction::GoTo(address),
+        2 => Instruction::Call(address),
+        3 => Instruction::SkipEq((opcode_regx(code), byte2)),
+        4 => Instruction::SkipNeq((opcode_regx(code), byte2)),
+        5 => {
+            match nibble4 {
+                0 => Instruction::SkipEqR((opcode_regx(code), opcode_regy(code))),
+                2 => Instruction::SaveRange((opcode_regx(code), opcode_regy(code))),
+                3 => Instruction::LoadRange((opcode_regx(code), opcode_regy(code))),
+                _ => Instruction::Nope(code),
+            }
+        }
+        6 => Instruction::SetR((opcode_regx(code), byte2)),
+        7 => Instruction::AddR((opcode_regx(code), byte2)),
+        8 => {
+            match nibble4 {
+                0 => Instruction::AssignR((opcode_regx(code), opcode_regy(code))),
+                1 => Instruction::OrR((opcode_regx(code), opcode_regy(code))),
+                2 => Instruction::AndR((opcode_regx(code), opcode_regy(code))),
+                3 => Instruction::XorR((opcode_regx(code), opcode_regy(code))),
+                4 => Instruction::AddR2((opcode_regx(code), opcode_regy(code))),
+                5 => Instruction::SubR((opcode_regx(code), opcode_regy(code))),
+                6 => Instruction::RShiftR((opcode_regx(code), opcode_regy(code))),
+                7 => Instruction::SubR2((opcode_regx(code), opcode_regy(code))),
+                0xE => Instruction::LShiftR((opcode_regx(code), opcode_regy(code))),
+                _ => Instruction::Nope(code),
+            }
+        }
+        9 => Instruction::SkipNeqR((opcode_regx(code), opcode_regy(code))),
+        0xA => Instruction::Mem(address),
+        0xB => Instruction::Jmp(address),
+        0xC => Instruction::Rand((opcode_regx(code), byte2)),
+        0xD => Instruction::Disp((opcode_regx(code), opcode_regy(code), nibble4)),
+        0xE => {
+            match byte2 {
+                0x9E => Instruction::KeyPress(opcode_regx(code)),
+                0xA1 => Instruction::KeyNoPress(opcode_regx(code)),
+                _ => Instruction::Nope(code),
+            }
+        }
+        0xF => {
+            match byte2 {
+                0x00 if opcode_regx(code) == 0 => Instruction::LongMem,
+                0x01 => Instruction::Plane(opcode_regx(code) as u8),
+                0x02 if opcode_regx(code) == 0 => Instruction::Audio,
+                0x07 => Instruction::GetDelay(opcode_regx(code)),
+                0x0A => Instruction::WaitKey(opcode_regx(code)),
+                0x15 => Instruction::SetDelay(opcode_regx(code)),
+                0x18 => Instruction::SetSound(opcode_regx(code)),
+                0x1E => Instruction::AddM(opcode_regx(code)),
+                0x29 => Instruction::Sprite(opcode_regx(code)),
+                0x30 => Instruction::BigSprite(opcode_regx(code)),
+                0x3A => Instruction::Pitch(opcode_regx(code)),
+                0x33 => Instruction::Bcd(opcode_regx(code)),
+                0x55 => Instruction::DumpR(opcode_regx(code)),
+                0x65 => Instruction::LoadR(opcode_regx(code)),
+                0x75 => Instruction::SaveFlags(opcode_regx(code)),
+                0x85 => Instruction::LoadFlags(opcode_regx(code)),
+                _ => Instruction::Nope(code),
+            }
+        }
+        _ => Instruction::Nope(code),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Nope(code) => write!(f, "DW {:#06X}", code),
+            Instruction::Sys(address) => write!(f, "SYS {:#05X}", address),
+            Instruction::Clr => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LoRes => write!(f, "LOW"),
+            Instruction::HiRes => write!(f, "HIGH"),
+            Instruction::GoTo(address) => write!(f, "JP {:#05X}", address),
+            Instruction::Call(address) => write!(f, "CALL {:#05X}", address),
+            Instruction::SkipEq((x, nn)) => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipNeq((x, nn)) => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipEqR((x, y)) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange((x, y)) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange((x, y)) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::SetR((x, nn)) => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::AddR((x, nn)) => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::AssignR((x, y)) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::OrR((x, y)) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::AndR((x, y)) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::XorR((x, y)) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddR2((x, y)) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubR((x, y)) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::RShiftR((x, y)) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubR2((x, y)) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::LShiftR((x, y)) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNeqR((x, y)) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::Mem(address) => write!(f, "LD I, {:#05X}", address),
+            Instruction::Jmp(address) => write!(f, "JP V0, {:#05X}", address),
+            Instruction::Rand((x, nn)) => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::Disp((x, y, n)) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::KeyPress(x) => write!(f, "SKP V{:X}", x),
+            Instruction::KeyNoPress(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LongMem => write!(f, "LD I, LONG"),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::GetDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddM(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::Sprite(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::BigSprite(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::DumpR(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadR(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::SaveFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+/// One line of a ROM listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// Where the bytes are loaded in memory
+    pub address: u16,
+
+    /// Two bytes for most instructions, four for `F000 NNNN`, and one for
+    /// a trailing odd byte
+    pub bytes: Vec<u8>,
+
+    /// The decoded instruction; `None` for a trailing odd byte
+    pub instruction: Option<Instruction>,
+}
+
+impl Line {
+    /// Just the mnemonic, which the assembler turns back into `bytes`
+    pub fn text(&self) -> String {
+        match (self.instruction, self.bytes.len()) {
+            (Some(Instruction::LongMem), 4) => {
+                let address = (self.bytes[2] as u16) << 8 | self.bytes[3] as u16;
+                format!("LD I, LONG {:#06X}", address)
+            }
+            (Some(i), _) => i.to_string(),
+            (None, _) => format!("DB {:#04X}", self.bytes[0]),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    /// Address, bytes and mnemonic, as in `0200  6120       LD V1, 0x20`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex: Vec<String> = self.bytes
+            .chunks(2)
+            .map(|w| w.iter().map(|b| format!("{:02X}", b)).collect())
+            .collect();
+        write!(f, "{:04X}  {:<9}  {}", self.address, hex.join(" "), self.text())
+    }
+}
+
+/// Disassemble a whole ROM, as loaded at 0x200, one instruction after the
+/// other. Data mixed in with the code is decoded as if it were instructions
+pub fn disassemble(rom: &[u8]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = (0x200 + offset) as u16;
+        if offset + 1 == rom.len() {
+            lines.push(Line {
+                address,
+                bytes: vec![rom[offset]],
+                instruction: None,
+            });
+            break;
+        }
+        let code = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+        let mut instruction = decode(code);
+        let len = match instruction {
+            Instruction::LongMem if offset + 4 <= rom.len() => 4,
+            // The address is cut off, so this is not an instruction
+            Instruction::LongMem => {
+                instruction = Instruction::Nope(code);
+                2
+            }
+            // The CPU ignores the low nibble of 9XY0, but SNE would
+            // assemble back to a different word
+            Instruction::SkipNeqR(_) if code & 0xF != 0 => {
+                instruction = Instruction::Nope(code);
+                2
+            }
+            _ => 2,
+        };
+        lines.push(Line {
+            address,
+            bytes: rom[offset..offset + len].to_vec(),
+            instruction: Some(instruction),
+        });
+        offset += len;
+    }
+    lines
+}
+
+#[inline]
+/// Return the most significant nibble
+pub(crate) fn opcode_n1(code: u16) -> u8 {
+    (code >> 12) as u8
+}
+
+#[inline]
+/// return the least significant 12 bits
+pub(crate) fn opcode_addr(code: u16) -> u16 {
+    code << 4 >> 4
+}
+
+#[inline]
+/// Return the second nibble
+pub(crate) fn opcode_regx(code: u16) -> usize {
+    (code << 4 >> 12) as usize
+}
+
+#[inline]
+/// Return the third nibble
+pub(crate) fn opcode_regy(code: u16) -> usize {
+    (code << 8 >> 12) as usize
+}
+
+#[inline]
+/// return the forth nibble
+pub(crate) fn opcode_nibble4(code: u16) -> u8 {
+    (code << 12 >> 12) as u8
+}
+
+#[inline]
+/// Return the second byte
+pub(crate) fn opcode_byte2(code: u16) -> u8 {
+    (code << 8 >> 8) as u8
+}
@@ -0,0 +1,226 @@
+/// Low resolution mode, the only one on the original CHIP-8
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+
+/// SUPER-CHIP's high resolution mode
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// XO-CHIP bitplanes; plain CHIP-8 programs only ever draw on the first
+pub const PLANES: usize = 4;
+
+/// Dirty rectangles kept before they are merged into one for the whole
+/// screen
+const MAX_DIRTY: usize = 16;
+
+/// An area of the screen, in pixels of the current resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// The screen, in whichever resolution the program last selected. Pixels
+/// are stored row-major, `width() * height()` of them, each a bitmask of the
+/// planes lit at that position: 0 is background, and bit N set means plane N
+/// is lit. Frontends pick a color for each of the 16 combinations.
+///
+/// The framebuffer also remembers which areas changed, until the host takes
+/// them with `Chip8::take_dirty`
+#[derive(Clone)]
+pub struct Framebuffer {
+    hires: bool,
+    pixels: [u8; HIRES_WIDTH * HIRES_HEIGHT],
+    dirty: Vec<Rect>,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Framebuffer {
+            hires: false,
+            pixels: [0; HIRES_WIDTH * HIRES_HEIGHT],
+            dirty: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            LORES_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    /// True in SUPER-CHIP's 128x64 mode
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Every pixel of the current resolution, row by row
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels[..self.width() * self.height()]
+    }
+
+    /// The planes lit at (x, y)
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width() + x]
+    }
+
+    /// True if anything changed since the dirty areas were last taken
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// The areas that changed since they were last taken. They may overlap
+    pub fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty
+    }
+
+    pub(crate) fn take_dirty(&mut self) -> Vec<Rect> {
+        ::std::mem::take(&mut self.dirty)
+    }
+
+    /// Note that the `width` by `height` area at (x, y) changed. With
+    /// `wrap`, the parts past the right and bottom edges are on the
+    /// opposite side; without, they are off screen
+    pub(crate) fn mark(&mut self, x: usize, y: usize, width: usize, height: usize, wrap: bool) {
+        let (w, h) = (self.width(), self.height());
+        let split = |start: usize, len: usize, max: usize| {
+            let first = (start, len.min(max - start));
+            let rest = start + len - max.min(start + len);
+            if wrap && rest > 0 { vec![first, (0, rest.min(max))] } else { vec![first] }
+        };
+        for &(x, width) in &split(x, width, w) {
+            for &(y, height) in &split(y, height, h) {
+                self.add_dirty(Rect { x, y, width, height });
+            }
+        }
+    }
+
+    /// Note that the whole screen changed
+    pub(crate) fn mark_all(&mut self) {
+        self.dirty.clear();
+        let (width, height) = (self.width(), self.height());
+        self.dirty.push(Rect { x: 0, y: 0, width, height });
+    }
+
+    fn add_dirty(&mut self, rect: Rect) {
+        let whole = Rect { x: 0, y: 0, width: self.width(), height: self.height() };
+        if self.dirty.contains(&whole) || self.dirty.contains(&rect) {
+            return;
+        }
+        if self.dirty.len() == MAX_DIRTY {
+            self.mark_all();
+        } else {
+            self.dirty.push(rect);
+        }
+    }
+
+    /// Switch resolution; this also clears every plane
+    pub(crate) fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels = [0; HIRES_WIDTH * HIRES_HEIGHT];
+        self.mark_all();
+    }
+
+    /// Overwrite the pixels of the current resolution, as from `pixels()`
+    pub(crate) fn restore(&mut self, pixels: &[u8]) {
+        self.pixels[..pixels.len()].copy_from_slice(pixels);
+        self.mark_all();
+    }
+
+    /// Blank the planes in `mask`, leaving the others alone
+    pub(crate) fn clear(&mut self, mask: u8) {
+        for p in self.pixels.iter_mut() {
+            *p &= !mask;
+        }
+        self.mark_all();
+    }
+
+    /// XOR the pixel at (x, y) on `plane`, a single plane bit, returning true
+    /// if it was lit beforehand
+    pub(crate) fn toggle(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let i = y * self.width() + x;
+        let was = self.pixels[i] & plane != 0;
+        self.pixels[i] ^= plane;
+        was
+    }
+
+    /// Move the planes in `mask` down by `n` rows, blanking the rows
+    /// scrolled in
+    pub(crate) fn scroll_down(&mut self, n: usize, mask: u8) {
+        let (w, h) = (self.width(), self.height());
+        let n = n.min(h);
+        for y in (0..h).rev() {
+            for x in 0..w {
+                let from = if y >= n { self.get(x, y - n) } else { 0 };
+                self.blit(x, y, from, mask);
+            }
+        }
+        self.mark_all();
+    }
+
+    /// Move the planes in `mask` up by `n` rows, blanking the rows scrolled
+    /// in
+    pub(crate) fn scroll_up(&mut self, n: usize, mask: u8) {
+        let (w, h) = (self.width(), self.height());
+        let n = n.min(h);
+        for y in 0..h {
+            for x in 0..w {
+                let from = if y + n < h { self.get(x, y + n) } else { 0 };
+                self.blit(x, y, from, mask);
+            }
+        }
+        self.mark_all();
+    }
+
+    /// Move the planes in `mask` right by `n` columns, blanking the columns
+    /// scrolled in
+    pub(crate) fn scroll_right(&mut self, n: usize, mask: u8) {
+        let (w, h) = (self.width(), self.height());
+        let n = n.min(w);
+        for y in 0..h {
+            for x in (0..w).rev() {
+                let from = if x >= n { self.get(x - n, y) } else { 0 };
+                self.blit(x, y, from, mask);
+            }
+        }
+        self.mark_all();
+    }
+
+    /// Move the planes in `mask` left by `n` columns, blanking the columns
+    /// scrolled in
+    pub(crate) fn scroll_left(&mut self, n: usize, mask: u8) {
+        let (w, h) = (self.width(), self.height());
+        let n = n.min(w);
+        for y in 0..h {
+            for x in 0..w {
+                let from = if x + n < w { self.get(x + n, y) } else { 0 };
+                self.blit(x, y, from, mask);
+            }
+        }
+        self.mark_all();
+    }
+
+    /// Replace the planes in `mask` at (x, y) with those of `from`
+    fn blit(&mut self, x: usize, y: usize, from: u8, mask: u8) {
+        let i = y * self.width() + x;
+        self.pixels[i] = self.pixels[i] & !mask | from & mask;
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Framebuffer::new()
+    }
+}
@@ -0,0 +1,474 @@
+//! A stub for GDB's remote serial protocol, so a debugger can attach to a
+//! running program with `target remote localhost:<port>`.
+//!
+//! GDB has no CHIP-8 architecture, so the register file is described to it
+//! with a target description: V0-VF, then I and PC (16 bits, little-endian),
+//! then SP (the call depth) and the delay and sound timers. All of memory is
+//! readable and writable. Breakpoints (`Z0`) and write, read and access
+//! watchpoints (`Z2`-`Z4`) map onto the `Debugger`.
+//!
+//! The description names Z80 as the architecture, which has the same 8-bit
+//! registers and 16-bit little-endian addresses, so GDB needs to be built
+//! with Z80 support, as `gdb-multiarch` 11 and later are:
+//!
+//! ```text
+//! gdb-multiarch -ex 'target remote localhost:<port>'
+//! ```
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use super::{Beeper, Chip8, Chip8Error, Debugger, Display, Input, Stop, Watchpoint};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>z80</architecture>
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Register numbers, in the order of the target description
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+const REG_COUNT: usize = 21;
+
+struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+/// Listens for one GDB at a time. Hosts call `poll` and `run` once per
+/// frame, the latter in place of `Chip8::cycle`
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<Client>,
+    debugger: Debugger,
+}
+
+impl GdbStub {
+    /// Listen on localhost; port 0 picks a free one
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            listener,
+            client: None,
+            debugger: Debugger::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// True while a debugger is attached
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// True while the attached debugger has the program stopped; hosts
+    /// should not tick the timers then
+    pub fn is_paused(&self) -> bool {
+        !self.debugger.is_running()
+    }
+
+    /// Accept a connection if there is none, and answer whatever packets
+    /// have arrived. Never blocks
+    pub fn poll<D: Display, I: Input, B: Beeper>(
+        &mut self,
+        c: &mut Chip8<D, I, B>,
+    ) -> io::Result<()> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nodelay(true)?;
+                    self.client = Some(Client {
+                        stream,
+                        buf: Vec::new(),
+                    });
+                    // GDB expects the program to be stopped when it attaches
+                    self.debugger.pause();
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let closed = {
+            let client = self.client.as_mut().unwrap();
+            client.stream.set_nonblocking(true)?;
+            let mut chunk = [0; 1024];
+            let closed = loop {
+                match client.stream.read(&mut chunk) {
+                    Ok(0) => break true,
+                    Ok(n) => client.buf.extend_from_slice(&chunk[..n]),
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break false,
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(_) => break true,
+                }
+            };
+            client.stream.set_nonblocking(false)?;
+            closed
+        };
+
+        while let Some(packet) = self.next_packet()? {
+            if let Some(reply) = self.handle(&packet, c) {
+                self.send(&reply)?;
+            }
+            if self.client.is_none() {
+                return Ok(());
+            }
+        }
+        if closed {
+            self.detach();
+        }
+        Ok(())
+    }
+
+    /// Execute up to `cycles` instructions unless GDB has the program
+    /// stopped, reporting to it when execution stops. Returns false once the
+    /// program has exited. Errors are reported to an attached GDB as an
+    /// illegal instruction, leaving the program stopped for inspection
+    pub fn run<D: Display, I: Input, B: Beeper>(
+        &mut self,
+        c: &mut Chip8<D, I, B>,
+        cycles: usize,
+    ) -> Result<bool, Chip8Error> {
+        let stop = match self.debugger.run(c, cycles) {
+            Ok(stop) => stop,
+            Err(_) if self.client.is_some() => {
+                // Sending can only fail if GDB went away, which `poll` notices
+                let _ = self.send("S04");
+                return Ok(true);
+            }
+            Err(e) => return Err(e),
+        };
+        let reply = match stop {
+            None => return Ok(true),
+            Some(Stop::Halted) => {
+                let _ = self.send("W00");
+                return Ok(false);
+            }
+            Some(stop) => stop_reply(stop),
+        };
+        let _ = self.send(&reply);
+        Ok(true)
+    }
+
+    /// Take the next complete packet out of the buffer, acknowledging it.
+    /// An interrupt from GDB is returned as the packet "\x03"
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        let client = match self.client {
+            Some(ref mut client) => client,
+            None => return Ok(None),
+        };
+        loop {
+            let start = match client.buf.iter().position(|b| *b == b'$' || *b == 0x03) {
+                Some(start) => start,
+                None => {
+                    client.buf.clear();
+                    return Ok(None);
+                }
+            };
+            if client.buf[start] == 0x03 {
+                client.buf.drain(..start + 1);
+                return Ok(Some("\x03".to_string()));
+            }
+            let end = match client.buf[start..].iter().position(|b| *b == b'#') {
+                Some(end) if start + end + 2 < client.buf.len() => start + end,
+                _ => {
+                    client.buf.drain(..start);
+                    return Ok(None);
+                }
+            };
+            let body = client.buf[start + 1..end].to_vec();
+            let sum = String::from_utf8_lossy(&client.buf[end + 1..end + 3]).to_string();
+            client.buf.drain(..end + 3);
+            if u8::from_str_radix(&sum, 16).ok() == Some(checksum(&body)) {
+                client.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&body).to_string()));
+            }
+            client.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        if let Some(ref mut client) = self.client {
+            let packet = format!("${}#{:02x}", reply, checksum(reply.as_bytes()));
+            client.stream.write_all(packet.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Forget GDB's breakpoints and let the program run free
+    fn detach(&mut self) {
+        self.client = None;
+        self.debugger = Debugger::new();
+    }
+
+    /// The reply to `packet`, if one is due now; continuing replies when
+    /// execution stops instead
+    fn handle<D: Display, I: Input, B: Beeper>(
+        &mut self,
+        packet: &str,
+        c: &mut Chip8<D, I, B>,
+    ) -> Option<String> {
+        let (kind, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match kind {
+            "\x03" => {
+                self.debugger.pause();
+                "S02".to_string()
+            }
+            "?" => "S05".to_string(),
+            "g" => (0..REG_COUNT).map(|r| read_register(c, r)).collect(),
+            "G" => {
+                // Check the whole payload before writing, so a bad one
+                // changes nothing; decode_hex also rules out non-ASCII
+                let widths: Vec<usize> =
+                    (0..REG_COUNT).map(|r| read_register(c, r).len()).collect();
+                let total: usize = widths.iter().sum();
+                if args.len() != total || decode_hex(args).is_none() {
+                    "E01".to_string()
+                } else {
+                    let mut rest = args;
+                    let mut ok = true;
+                    for (r, width) in widths.into_iter().enumerate() {
+                        let (hex, tail) = rest.split_at(width);
+                        ok &= write_register(c, r, hex);
+                        rest = tail;
+                    }
+                    if ok { "OK" } else { "E01" }.to_string()
+                }
+            }
+            "p" => {
+                match usize::from_str_radix(args, 16) {
+                    Ok(r) if r < REG_COUNT => read_register(c, r),
+                    _ => "E01".to_string(),
+                }
+            }
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                match (parts.next().map(|r| usize::from_str_radix(r, 16)), parts.next()) {
+                    (Some(Ok(r)), Some(value)) if r < REG_COUNT => {
+                        if write_register(c, r, value) {
+                            "OK".to_string()
+                        } else {
+                            "E01".to_string()
+                        }
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => {
+                match parse_range(args) {
+                    Some((address, len)) if in_memory(c, address, len) => {
+                        c.memory[address..address + len]
+                            .iter()
+                            .map(|b| format!("{:02x}", b))
+                            .collect()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let range = parts.next().and_then(parse_range);
+                let data = parts.next().and_then(decode_hex);
+                match (range, data) {
+                    (Some((address, len)), Some(ref data))
+                        if data.len() == len && in_memory(c, address, len) => {
+                        c.memory[address..address + len].copy_from_slice(data);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "Z" | "z" => {
+                let mut parts = args.split(',');
+                let kind = parts.next();
+                let address = parts.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+                let insert = packet.starts_with('Z');
+                match (kind, address) {
+                    (Some("0"), Some(a)) | (Some("1"), Some(a)) => {
+                        if insert {
+                            self.debugger.add_breakpoint(a);
+                        } else {
+                            self.debugger.remove_breakpoint(a);
+                        }
+                        "OK".to_string()
+                    }
+                    (Some(kind @ "2"), Some(a)) |
+                    (Some(kind @ "3"), Some(a)) |
+                    (Some(kind @ "4"), Some(a)) => {
+                        let mut watchpoints = Vec::new();
+                        if kind != "3" {
+                            watchpoints.push(Watchpoint::Write(a));
+                        }
+                        if kind != "2" {
+                            watchpoints.push(Watchpoint::Read(a));
+                        }
+                        for w in watchpoints {
+                            if insert {
+                                self.debugger.add_watchpoint(w);
+                            } else {
+                                self.debugger.remove_watchpoint(w);
+                            }
+                        }
+                        "OK".to_string()
+                    }
+                    _ => String::new(),
+                }
+            }
+            "s" => {
+                self.debugger.step();
+                return None;
+            }
+            "c" => {
+                self.debugger.resume();
+                return None;
+            }
+            "D" => {
+                let _ = self.send("OK");
+                self.detach();
+                return None;
+            }
+            "k" => {
+                self.detach();
+                return None;
+            }
+            "H" => "OK".to_string(),
+            "q" => self.query(args),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            return "PacketSize=4000;qXfer:features:read+".to_string();
+        }
+        if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            return match parse_range(range) {
+                Some((offset, len)) => {
+                    let rest = &TARGET_XML[offset.min(TARGET_XML.len())..];
+                    if rest.len() > len {
+                        format!("m{}", &rest[..len])
+                    } else {
+                        format!("l{}", rest)
+                    }
+                }
+                None => "E01".to_string(),
+            };
+        }
+        match args {
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Watchpoint { watchpoint: Watchpoint::Write(a), .. } => format!("T05watch:{:x};", a),
+        Stop::Watchpoint { watchpoint: Watchpoint::Read(a), .. } => format!("T05rwatch:{:x};", a),
+        Stop::Halted => "W00".to_string(),
+        _ => "S05".to_string(),
+    }
+}
+
+/// Register `r` as hex, in target byte order
+fn read_register<D: Display, I: Input, B: Beeper>(c: &Chip8<D, I, B>, r: usize) -> String {
+    match r {
+        0..=15 => format!("{:02x}", c.register[r]),
+        REG_I => format!("{:02x}{:02x}", c.address_reg as u8, c.address_reg >> 8),
+        REG_PC => format!("{:02x}{:02x}", c.pc as u8, c.pc >> 8),
+        REG_SP => format!("{:02x}", c.stack.len()),
+        REG_DT => format!("{:02x}", c.delay_timer),
+        REG_ST => format!("{:02x}", c.sound_timer),
+        _ => String::new(),
+    }
+}
+
+/// Set register `r` from hex in target byte order. The call depth cannot
+/// be changed, so writes to SP are ignored
+fn write_register<D: Display, I: Input, B: Beeper>(
+    c: &mut Chip8<D, I, B>,
+    r: usize,
+    hex: &str,
+) -> bool {
+    let bytes = match decode_hex(hex) {
+        Some(bytes) if !bytes.is_empty() && bytes.len() == read_register(c, r).len() / 2 => bytes,
+        _ => return false,
+    };
+    let word = || bytes[0] as u16 | (bytes.get(1).cloned().unwrap_or(0) as u16) << 8;
+    match r {
+        0..=15 => c.register[r] = bytes[0],
+        REG_I => c.address_reg = word(),
+        REG_PC => c.pc = word(),
+        REG_SP => {}
+        REG_DT => c.delay_timer = bytes[0],
+        REG_ST => c.sound_timer = bytes[0],
+        _ => return false,
+    }
+    true
+}
+
+/// Whether `len` bytes from `address` are all in memory
+fn in_memory<D: Display, I: Input, B: Beeper>(
+    c: &Chip8<D, I, B>,
+    address: usize,
+    len: usize,
+) -> bool {
+    address.checked_add(len).is_some_and(|end| end <= c.memory.len())
+}
+
+/// `addr,length` in hex
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let mut parts = s.splitn(2, ',');
+    let address = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((address, len))
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() & 1 == 1 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
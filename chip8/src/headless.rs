@@ -0,0 +1,119 @@
+//! Peripherals for running without speakers or keyboard, such as in CI: a
+//! beeper that does nothing, and input that plays back a script of key
+//! presses. For the screen, use `()` as the display and read
+//! `Chip8::framebuffer`
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::{Beeper, Input};
+
+/// A beeper that stays silent
+pub struct NullBeeper;
+
+impl Beeper for NullBeeper {
+    fn beep_on(&mut self) {}
+    fn beep_off(&mut self) {}
+}
+
+/// A key held down for `frames` frames, starting at frame `frame`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    pub frame: u64,
+    pub key: u8,
+    pub frames: u64,
+}
+
+impl KeyPress {
+    fn held_at(&self, frame: u64) -> bool {
+        self.frame <= frame && frame < self.frame + self.frames
+    }
+}
+
+/// Returned when parsing a key press that is not `frame:key[:frames]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidKeyPress(pub String);
+
+impl fmt::Display for InvalidKeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid key press '{}', expected frame:key[:frames] with the key in hex, e.g. 60:A",
+            self.0
+        )
+    }
+}
+
+impl FromStr for KeyPress {
+    type Err = InvalidKeyPress;
+
+    /// Parse `frame:key[:frames]`, the key in hex. Keys are held for one
+    /// frame unless `frames` says otherwise
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidKeyPress(s.to_string());
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(invalid());
+        }
+        let frame = parts[0].parse().map_err(|_| invalid())?;
+        let key = u8::from_str_radix(parts[1], 16).map_err(|_| invalid())?;
+        let frames = match parts.get(2) {
+            Some(n) => n.parse().map_err(|_| invalid())?,
+            None => 1,
+        };
+        if key > 0xF || frames == 0 {
+            return Err(invalid());
+        }
+        Ok(KeyPress { frame, key, frames })
+    }
+}
+
+/// Input that presses keys on a schedule. The host moves it along with
+/// `set_frame` before running each frame, which sets the keypad to the
+/// keys the script holds down at that frame. `set_key` still works in
+/// between
+pub struct ScriptedInput {
+    presses: Vec<KeyPress>,
+    frame: u64,
+    keys: u16,
+}
+
+impl ScriptedInput {
+    pub fn new(mut presses: Vec<KeyPress>) -> Self {
+        presses.sort_by_key(|p| p.frame);
+        let mut input = ScriptedInput {
+            presses,
+            frame: 0,
+            keys: 0,
+        };
+        input.set_frame(0);
+        input
+    }
+
+    pub fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+        self.keys = self.presses
+            .iter()
+            .filter(|p| p.held_at(frame))
+            .fold(0, |keys, p| keys | 1 << p.key);
+    }
+
+    /// True once every press in the script is over
+    pub fn is_finished(&self) -> bool {
+        self.presses.iter().all(|p| p.frame + p.frames <= self.frame)
+    }
+}
+
+impl Input for ScriptedInput {
+    fn keys(&self) -> u16 {
+        self.keys
+    }
+
+    fn set_key(&mut self, key: u8, pressed: bool) {
+        if pressed {
+            self.keys |= 1 << key;
+        } else {
+            self.keys &= !(1 << key);
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Images of the screen: PBM and PNG files, ASCII art, and animated GIFs
+
+use std::io::{self, Write};
+
+use framebuffer::Framebuffer;
+
+/// Color for each combination of lit XO-CHIP planes, indexed by the pixel's
+/// plane mask. Plain CHIP-8 only ever uses the first two
+pub const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xFF, 0xFF, 0xFF),
+    (0xFF, 0x66, 0x00),
+    (0xFF, 0xCC, 0x00),
+    (0x00, 0x99, 0xFF),
+    (0x99, 0xCC, 0xFF),
+    (0x66, 0x33, 0x99),
+    (0xCC, 0x99, 0xFF),
+    (0x00, 0x99, 0x33),
+    (0x99, 0xFF, 0x99),
+    (0x66, 0x66, 0x00),
+    (0xCC, 0xCC, 0x66),
+    (0x00, 0x66, 0x66),
+    (0x66, 0xCC, 0xCC),
+    (0x66, 0x66, 0x66),
+    (0xAA, 0xAA, 0xAA),
+];
+
+/// The names `palette` knows
+pub const PALETTE_NAMES: [&str; 4] = ["default", "amber", "green", "lcd"];
+
+/// A palette by name: `PALETTE`, or the look of an amber or green monitor or
+/// of a green LCD. The presets only set the background and the colors of
+/// XO-CHIP's two planes, keeping the default's for the rest
+pub fn palette(name: &str) -> Option<[(u8, u8, u8); 16]> {
+    let colors: &[(u8, u8, u8)] = match name.to_lowercase().as_str() {
+        "default" => &[],
+        "amber" => &[
+            (0x1A, 0x10, 0x00),
+            (0xFF, 0xB0, 0x00),
+            (0x80, 0x58, 0x00),
+            (0xFF, 0xD8, 0x80),
+        ],
+        "green" => &[
+            (0x00, 0x14, 0x00),
+            (0x33, 0xFF, 0x33),
+            (0x1A, 0x80, 0x1A),
+            (0x99, 0xFF, 0x99),
+        ],
+        "lcd" => &[
+            (0x9B, 0xBC, 0x0F),
+            (0x0F, 0x38, 0x0F),
+            (0x30, 0x62, 0x30),
+            (0x8B, 0xAC, 0x0F),
+        ],
+        _ => return None,
+    };
+    let mut palette = PALETTE;
+    palette[..colors.len()].copy_from_slice(colors);
+    Some(palette)
+}
+
+/// The plane mask of each pixel of `grid` blown up `scale` times, row-major
+fn scaled(grid: &Framebuffer, scale: usize) -> (usize, usize, Vec<u8>) {
+    let (width, height) = (grid.width() * scale, grid.height() * scale);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(grid.get(x / scale, y / scale));
+        }
+    }
+    (width, height, pixels)
+}
+
+/// A binary PBM, each pixel `scale` times as big. Lit pixels, on any plane,
+/// are black
+pub fn pbm(grid: &Framebuffer, scale: usize) -> Vec<u8> {
+    let (width, height, pixels) = scaled(grid, scale);
+    let mut out = format!("P4\n{} {}\n", width, height).into_bytes();
+    for row in pixels.chunks(width) {
+        for byte in row.chunks(8) {
+            let bits = byte.iter().enumerate().fold(0, |acc, (i, p)| {
+                if *p != 0 { acc | 0x80 >> i } else { acc }
+            });
+            out.push(bits);
+        }
+    }
+    out
+}
+
+/// An indexed-color PNG, each pixel `scale` times as big and colored by
+/// `palette`. The image data is stored without compression, which for
+/// screens this size costs less than a deflate encoder would
+pub fn png(grid: &Framebuffer, palette: &[(u8, u8, u8); 16], scale: usize) -> Vec<u8> {
+    let (width, height, pixels) = scaled(grid, scale);
+    indexed_png(width, height, &pixels, palette)
+}
+
+/// A PNG of `pixels`, row-major palette indices
+pub(crate) fn indexed_png(
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+    palette: &[(u8, u8, u8); 16],
+) -> Vec<u8> {
+    let mut out = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per pixel, indexed color, default compression, filtering and
+    // no interlacing
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    chunk(&mut out, b"IHDR", &header);
+
+    let colors: Vec<u8> = palette.iter().flat_map(|&(r, g, b)| vec![r, g, b]).collect();
+    chunk(&mut out, b"PLTE", &colors);
+
+    // Each row starts with filter type 0, none
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width) {
+        raw.push(0);
+        raw.extend(row.iter().map(|p| p & 0xF));
+    }
+    chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+/// An animated GIF being written, a frame at a time, with a 16 color
+/// palette. It loops forever
+pub struct Gif<W: Write> {
+    out: W,
+    width: u16,
+    height: u16,
+}
+
+impl<W: Write> Gif<W> {
+    /// Write the header of a `width` by `height` GIF
+    pub fn new(
+        mut out: W,
+        width: u16,
+        height: u16,
+        palette: &[(u8, u8, u8); 16],
+    ) -> io::Result<Self> {
+        out.write_all(b"GIF89a")?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        // A global color table of 2^(3 + 1) colors, 8 bits per channel;
+        // background color 0 and square pixels
+        out.write_all(&[0xF3, 0, 0])?;
+        for &(r, g, b) in palette.iter() {
+            out.write_all(&[r, g, b])?;
+        }
+        // The NETSCAPE2.0 extension, looping forever
+        out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        Ok(Gif { out, width, height })
+    }
+
+    /// Add a frame of row-major palette indices, covering the whole image,
+    /// shown for `delay` hundredths of a second
+    pub fn frame(&mut self, pixels: &[u8], delay: u16) -> io::Result<()> {
+        assert_eq!(self.width as usize * self.height as usize, pixels.len());
+        // Graphic control extension: no disposal, no transparency
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+        // Image descriptor: at 0, 0, the full size, no local color table
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&self.width.to_le_bytes())?;
+        self.out.write_all(&self.height.to_le_bytes())?;
+        self.out.write_all(&[0x00])?;
+
+        self.out.write_all(&[GIF_MIN_CODE_SIZE])?;
+        for block in lzw(pixels, GIF_MIN_CODE_SIZE).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])
+    }
+
+    /// Write the trailer, returning the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Enough bits for the 16 palette indices
+const GIF_MIN_CODE_SIZE: u8 = 4;
+
+/// GIF's variable-length LZW code stream for `indices`, each below
+/// `1 << min_size`, packed least significant bit first
+pub(crate) fn lzw(indices: &[u8], min_size: u8) -> Vec<u8> {
+    const MAX_CODES: u16 = 4096;
+    let clear = 1u16 << min_size;
+    let end = clear + 1;
+
+    // The code for each string already seen, by the code of the string
+    // without its last index and that index; 0 where there is none yet
+    let mut table = vec![0u16; (MAX_CODES as usize) << min_size];
+    let mut next = end + 1;
+    let mut size = min_size + 1;
+
+    let mut bits = Bits::default();
+    bits.put(clear, size);
+    let mut pixels = indices.iter();
+    if let Some(&first) = pixels.next() {
+        let mut prefix = first as u16;
+        for &k in pixels {
+            let slot = ((prefix as usize) << min_size) | k as usize;
+            if table[slot] != 0 {
+                prefix = table[slot];
+                continue;
+            }
+            bits.put(prefix, size);
+            // Decoders widen codes as soon as the table fills the current
+            // size
+            if next == 1 << size && size < 12 {
+                size += 1;
+            }
+            if next < MAX_CODES {
+                table[slot] = next;
+                next += 1;
+            } else {
+                bits.put(clear, size);
+                for t in table.iter_mut() {
+                    *t = 0;
+                }
+                next = end + 1;
+                size = min_size + 1;
+            }
+            prefix = k as u16;
+        }
+        bits.put(prefix, size);
+        if next == 1 << size && size < 12 {
+            size += 1;
+        }
+    }
+    bits.put(end, size);
+    bits.finish()
+}
+
+/// Packs codes of varying width into bytes, least significant bit first
+#[derive(Default)]
+struct Bits {
+    out: Vec<u8>,
+    acc: u32,
+    count: u32,
+}
+
+impl Bits {
+    fn put(&mut self, code: u16, size: u8) {
+        self.acc |= (code as u32) << self.count;
+        self.count += size as u32;
+        while self.count >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// The screen as text, a character per pixel: `.` is background, `#` is
+/// the first plane and other combinations of XO-CHIP planes are their
+/// mask in hex
+pub fn ascii(grid: &Framebuffer) -> String {
+    let mut out = String::with_capacity((grid.width() + 1) * grid.height());
+    for y in 0..grid.height() {
+        for x in 0..grid.width() {
+            out.push(match grid.get(x, y) {
+                0 => '.',
+                1 => '#',
+                mask => ::std::char::from_digit(mask as u32 & 0xF, 16).unwrap(),
+            });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Append a PNG chunk: length, type, data and the CRC of type and data
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
@@ -1,7 +1,34 @@
 extern crate rand;
 
-use std::time::{Duration, Instant};
-use rand::Rng;
+use std::error::Error;
+use std::fmt;
+
+use disasm::{decode, opcode_byte2, opcode_regx, Instruction};
+
+pub mod asm;
+pub mod disasm;
+mod debugger;
+mod framebuffer;
+pub mod gdb;
+pub mod headless;
+pub mod image;
+mod quirks;
+pub mod record;
+mod rewind;
+mod rng;
+mod sha1;
+pub mod sound;
+mod state;
+mod trace;
+
+pub use debugger::{Debugger, Register, Stop, Watchpoint};
+pub use framebuffer::{Framebuffer, Rect, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH, PLANES};
+pub use quirks::{Quirks, UnknownPreset};
+pub use rewind::Rewind;
+pub use rng::Xorshift;
+pub use sha1::sha1;
+pub use state::{rom_hash, StateError, STATE_VERSION};
+pub use trace::{AddressRange, InvalidRange, Trace};
 
 /// Register constants
 pub const V0: usize = 0x0;
@@ -41,25 +68,214 @@ const CHIP8_FONTS: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80  //F
 ];
 
+/// Where the SUPER-CHIP 8x10 digits live, right after the small font
+const BIG_FONT_ADDR: usize = 80;
+
+// from Octo, https://github.com/JohnEarnest/Octo
+const SCHIP_FONTS: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, //0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, //1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, //2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, //3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, //4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, //5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, //6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, //7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, //8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, //9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, //A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, //B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, //C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, //D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, //E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  //F
+];
+
+/// Errors raised while loading or executing a ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The opcode at `address` does not decode to any known instruction
+    InvalidOpcode { opcode: u16, address: u16 },
+
+    /// `RET` executed at `address` with an empty call stack
+    StackUnderflow { address: u16 },
+
+    /// `CALL` executed at `address` with all 24 stack frames in use
+    StackOverflow { address: u16 },
+
+    /// The ROM does not fit between 0x200 and the end of memory
+    RomTooLarge { size: usize, max: usize },
+
+    /// The instruction at `pc` touched memory past the end of the address
+    /// space
+    MemoryOutOfRange { address: usize, pc: u16 },
+
+    /// `Display::draw` failed
+    Display(String),
+
+    /// Writing to the trace failed
+    Trace(String),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::InvalidOpcode { opcode, address } => {
+                write!(
+                    f,
+                    "Invalid instruction '{:#X} {:#X}' at PC '{:#X}'",
+                    opcode >> 8,
+                    opcode_byte2(opcode),
+                    address
+                )
+            }
+            Chip8Error::StackUnderflow { address } => {
+                write!(f, "Return with an empty call stack at PC '{:#X}'", address)
+            }
+            Chip8Error::StackOverflow { address } => {
+                write!(f, "Call with a full call stack at PC '{:#X}'", address)
+            }
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, but at most {} bytes fit", size, max)
+            }
+            Chip8Error::MemoryOutOfRange { address, pc } => {
+                write!(
+                    f,
+                    "Memory access out of range at '{:#X}' by PC '{:#X}'",
+                    address,
+                    pc
+                )
+            }
+            Chip8Error::Display(ref e) => write!(f, "Display error: {}", e),
+            Chip8Error::Trace(ref e) => write!(f, "Trace error: {}", e),
+        }
+    }
+}
+
+impl Error for Chip8Error {}
+
+/// Rate, in Hz, at which the host should call `Chip8::tick_timers`
+pub const TIMER_HZ: u32 = 60;
+
+/// Instructions per frame that most programs run well at, 600 per second
+pub const DEFAULT_IPF: usize = 10;
+
+/// What happened during a `Chip8::run_frame`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameResult {
+    /// The CPU has halted; further frames do nothing
+    pub halted: bool,
+
+    /// `FX0A` is waiting for a key to be pressed and released
+    pub waiting_for_key: bool,
+
+    /// The screen changed during the frame, and was presented at its end
+    pub display_dirty: bool,
+}
+
 pub trait Beeper {
     fn beep_on(&mut self);
     fn beep_off(&mut self);
+
+    /// XO-CHIP: from now on, play `pattern` instead of the default tone while
+    /// the beeper is on. The pattern is 128 1-bit samples, most significant
+    /// bit first, looped at `rate` samples per second. Beepers with a single
+    /// tone can ignore it
+    fn set_pattern(&mut self, _pattern: &[u8; 16], _rate: f32) {}
+
+    /// Emulated time has reached `seconds` since the machine started. It is
+    /// called at every timer tick and before every other call, so beepers
+    /// that render sound offline can place each change to the sample.
+    /// Beepers playing in real time can ignore it
+    fn advance_to(&mut self, _seconds: f64) {}
+}
+
+impl Beeper for () {
+    fn beep_on(&mut self) {}
+    fn beep_off(&mut self) {}
 }
 
+/// A beeper that may not be there, such as an optional recording
+impl<B: Beeper> Beeper for Option<B> {
+    fn beep_on(&mut self) {
+        if let Some(ref mut b) = *self {
+            b.beep_on();
+        }
+    }
+
+    fn beep_off(&mut self) {
+        if let Some(ref mut b) = *self {
+            b.beep_off();
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], rate: f32) {
+        if let Some(ref mut b) = *self {
+            b.set_pattern(pattern, rate);
+        }
+    }
+
+    fn advance_to(&mut self, seconds: f64) {
+        if let Some(ref mut b) = *self {
+            b.advance_to(seconds);
+        }
+    }
+}
+
+/// Pushes the screen to the host whenever it changes; once per frame under
+/// `run_frame`. Hosts that would rather pull `Chip8::framebuffer` when
+/// `Chip8::take_dirty` says so can use `()`, which ignores it all
 pub trait Display {
-    fn clear(&mut self);
+    fn clear(&mut self) {}
 
-    fn draw(&mut self, grid: &[bool; 32 * 64]) -> Result<(), std::string::String>;
+    /// Present the whole screen; `grid` is 64x32 or, in SUPER-CHIP's
+    /// hi-res mode, 128x64
+    fn draw(&mut self, _grid: &Framebuffer) -> Result<(), std::string::String> {
+        Ok(())
+    }
 }
 
+impl Display for () {}
+
+/// The 16-key keypad. Hosts keep it up to date by calling `set_key`, or
+/// `Chip8::set_key`, as keys go down and up, and the CPU reads the state
+/// from `keys`
 pub trait Input {
-    /// Block until the next key press; return that key
-    fn block_for(&mut self) -> Option<u8>;
+    /// The keys held down as a bitmap: bit K is set while key K is
+    fn keys(&self) -> u16;
 
-    /// Return true if the given key is currently pressed
-    fn key(&mut self, key: u8) -> bool;
+    /// Press or release key `key`, 0 to F
+    fn set_key(&mut self, key: u8, pressed: bool);
 }
 
+/// The COSMAC VIP keypad on the left of a QWERTY keyboard, as the character
+/// on each host key and the CHIP-8 key it presses:
+///
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D      Q W E R
+/// 7 8 9 E  ->  A S D F
+/// A 0 B F      Z X C V
+/// ```
+pub const QWERTY_KEYPAD: [(char, u8); 16] = [
+    ('1', 0x1),
+    ('2', 0x2),
+    ('3', 0x3),
+    ('4', 0xC),
+    ('q', 0x4),
+    ('w', 0x5),
+    ('e', 0x6),
+    ('r', 0xD),
+    ('a', 0x7),
+    ('s', 0x8),
+    ('d', 0x9),
+    ('f', 0xE),
+    ('z', 0xA),
+    ('x', 0x0),
+    ('c', 0xB),
+    ('v', 0xF),
+];
+
 pub struct Chip8<D: Display, I: Input, B: Beeper> {
     /// V0 - VF CPU registers
     register: [u8; 16],
@@ -70,59 +286,122 @@ pub struct Chip8<D: Display, I: Input, B: Beeper> {
     /// I, register for address
     address_reg: u16,
 
+    /// Both timers count down once per `tick_timers`, at 60 Hz
     delay_timer: u8,
     sound_timer: u8,
 
+    /// Whether the beeper is currently on; it follows `sound_timer > 0`
+    beeping: bool,
+
     // equiv to original 48 byte stack, for up to 24 subroutine calls
     stack: Vec<u16>,
 
     /// 0-511:     originally the interpreter was here; 0-79 used for
-    /// font sprites. Rest is unused, and technically available to programs
+    /// font sprites, 80-239 for the SUPER-CHIP big font. Rest is unused,
+    /// and technically available to programs
     /// 512-3743:  ROM for instructions
     /// 3744-3839: call stack/internal use (not used by interpreter)
     /// 3840-4095: reserved for display refresh (not used by interpreter)
-    memory: [u8; 4096],
+    /// XO-CHIP extends this to 64 KiB, all of it available to programs
+    memory: Vec<u8>,
 
-    pub key: Option<u8>,
+    /// The key `FX0A` saw pressed, and now waits to see released
+    key: Option<u8>,
 
-    rng: rand::ThreadRng,
+    /// Randomly seeded unless the host calls `seed_rng`
+    rng: Xorshift,
 
-    // Beep sends a beep over the audio channel, returning true if it was
-    // successful and false otherwise. The CPU will panic if beep returns false
     beep: B,
 
     display: D,
-    grid: [bool; 32 * 64],
+    grid: Framebuffer,
+
+    /// XO-CHIP plane mask selected by `FN01`; drawing, clearing and
+    /// scrolling only touch these planes
+    plane: u8,
+
+    /// XO-CHIP audio pattern loaded by `F002`, and its pitch from `FX3A`
+    pattern: [u8; 16],
+    pitch: u8,
 
     input: I,
 
-    time: Instant,
+    /// SUPER-CHIP's RPL user flags, saved and restored by `FX75`/`FX85`
+    rpl: [u8; 16],
+
+    quirks: Quirks,
+
+    /// Set by `DXYN` under the display wait quirk; the CPU idles until the
+    /// next timer tick
+    vblank_wait: bool,
 
     halt: bool,
+
+    /// Set whenever the screen changes. Within `run_frame` the display is
+    /// only handed the screen once, at the end of the frame
+    display_dirty: bool,
+    in_frame: bool,
+
+    /// Hash of the loaded ROM, checked when restoring save states
+    rom_hash: u64,
+
+    /// When set, every data access by `read`/`write` is appended here, for
+    /// the debugger's watchpoints
+    accesses: Option<Vec<Access>>,
+
+    /// Instructions executed so far, and where to log each of them
+    cycles: u64,
+    trace: Option<Trace>,
+
+    /// Timer ticks so far, and how far through the current frame
+    /// `run_frame` has got; together, the emulated time the beeper is given
+    ticks: u64,
+    frame_pos: f64,
+}
+
+/// A memory access made by the instruction being executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    Read(usize),
+    Write(usize),
 }
 
 impl<D: Display, I: Input, B: Beeper> Chip8<D, I, B> {
-    pub fn new(display: D, input: I, beeper: B) -> Self {
-        let mut memory = [0; 4096];
-        for x in 0..80 {
-            memory[x] = CHIP8_FONTS[x];
-        }
+    pub fn new(display: D, input: I, beeper: B, quirks: Quirks) -> Self {
+        let size = if quirks.extended_memory { 0x10000 } else { 0x1000 };
+        let mut memory = vec![0; size];
+        memory[..80].copy_from_slice(&CHIP8_FONTS);
+        memory[BIG_FONT_ADDR..BIG_FONT_ADDR + 160].copy_from_slice(&SCHIP_FONTS);
         Chip8 {
             register: [0; 16],
             pc: 0x200,
             address_reg: 0,
             delay_timer: 0,
             sound_timer: 0,
+            beeping: false,
             stack: Vec::with_capacity(24),
-            memory: memory,
+            memory,
             key: None,
-            rng: rand::thread_rng(),
+            rng: Xorshift::new(rand::random()),
             beep: beeper,
-            display: display,
-            grid: [false; 32 * 64],
-            input: input,
-            time: Instant::now(),
+            display,
+            grid: Framebuffer::new(),
+            plane: 1,
+            pattern: [0; 16],
+            pitch: 64,
+            input,
+            rpl: [0; 16],
+            quirks,
+            vblank_wait: false,
             halt: false,
+            display_dirty: false,
+            in_frame: false,
+            rom_hash: rom_hash(&[]),
+            accesses: None,
+            cycles: 0,
+            ticks: 0,
+            frame_pos: 0.0,
+            trace: None,
         }
     }
 
@@ -143,185 +422,383 @@ impl<D: Display, I: Input, B: Beeper> Chip8<D, I, B> {
     //    }
     //}
 
+    /// Restart the random number generator from `seed`, making every `CXNN`
+    /// from here on reproducible
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Xorshift::new(seed);
+    }
+
+    /// V0 through VF
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.register
+    }
+
+    /// Address of the next instruction
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// The I register
+    pub fn address_reg(&self) -> u16 {
+        self.address_reg
+    }
+
+    /// Return addresses of the calls in progress, innermost last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The screen as the program last drew it
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.grid
+    }
+
+    /// The areas of the screen that changed since the last call, for hosts
+    /// that pull the screen once per frame rather than implement `Display`.
+    /// Empty if nothing changed
+    pub fn take_dirty(&mut self) -> Vec<Rect> {
+        self.grid.take_dirty()
+    }
+
+    pub fn input_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    pub fn beeper_mut(&mut self) -> &mut B {
+        &mut self.beep
+    }
+
+    /// Press or release key `key`, 0 to F. There is no key past F, so
+    /// anything higher is ignored
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if key < 16 {
+            self.input.set_key(key, pressed)
+        }
+    }
+
+    /// Whether key `key` is held down; there is no key past F
+    fn key_down(&self, key: u8) -> bool {
+        key < 16 && self.input.keys() & 1 << key != 0
+    }
+
     /// Insert a ROM into memory
-    pub fn load(&mut self, rom: Vec<u8>) {
-        if rom.len() > (4096 - 512) {
-            panic!("rom too big")
+    pub fn load(&mut self, rom: Vec<u8>) -> Result<(), Chip8Error> {
+        let max = self.memory.len() - 0x200;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
         }
 
-        for i in 0..rom.len() {
-            self.memory[i + 0x200] = rom[i]
+        self.memory[0x200..0x200 + rom.len()].copy_from_slice(&rom);
+        self.rom_hash = rom_hash(&rom);
+        Ok(())
+    }
+
+    /// Fetch, decode and execute one instruction. Returns false once the CPU
+    /// has halted
+    pub fn cycle(&mut self) -> Result<bool, Chip8Error> {
+        if self.vblank_wait {
+            return Ok(!self.halt);
+        }
+
+        if let Some(mut trace) = self.trace.take() {
+            if trace.wants(self.pc) {
+                if let Err(e) = trace.write_line(&self.trace_line()) {
+                    return Err(Chip8Error::Trace(e.to_string()));
+                }
+            }
+            self.trace = Some(trace);
         }
-        println!("Loaded {} bytes into memory", rom.len())
+
+        let code = self.if_()?;
+        let op = decode(code);
+        self.ex(op)?;
+        self.cycles += 1;
+
+        Ok(!self.halt)
     }
 
-    pub fn cycle(&mut self) -> bool {
-        let now = Instant::now();
-        if now.duration_since(self.time) > Duration::new(1, 0) {
-            if self.delay_timer > 0 {
-                self.delay_timer -= 1;
+    /// Run one frame of emulated time: up to `ipf` instructions, then a timer
+    /// tick. The frame ends early if the CPU halts, waits for the display or
+    /// waits for a key
+    pub fn run_frame(&mut self, ipf: usize) -> Result<FrameResult, Chip8Error> {
+        let mut frame = FrameResult::default();
+        self.display_dirty = false;
+        self.in_frame = true;
+        let ran = self.run_instructions(ipf);
+        self.in_frame = false;
+        self.frame_pos = 0.0;
+
+        frame.display_dirty = self.display_dirty;
+        if frame.display_dirty {
+            self.redraw()?;
+        }
+        frame.halted = !ran?;
+        if !frame.halted {
+            frame.waiting_for_key = self.waiting_for_key();
+            self.tick_timers();
+        }
+        Ok(frame)
+    }
+
+    /// The instructions of a frame; returns false if the CPU halted
+    fn run_instructions(&mut self, ipf: usize) -> Result<bool, Chip8Error> {
+        if self.halt {
+            return Ok(false);
+        }
+        for i in 0..ipf {
+            self.frame_pos = i as f64 / ipf as f64;
+            if self.vblank_wait {
+                break;
             }
-            if self.sound_timer > 0 {
+            let pc = self.pc;
+            if !self.cycle()? {
+                return Ok(false);
+            }
+            // Until the key comes, FX0A stays put and checks again next frame
+            if self.pc == pc && self.waiting_for_key() {
+                break;
+            }
+        }
+        Ok(true)
+    }
+
+    /// True while `FX0A` waits for a key to be pressed and released
+    pub fn waiting_for_key(&self) -> bool {
+        matches!(decode(self.next_opcode()), Instruction::WaitKey(_))
+    }
+
+    /// The instruction at PC, or 0 past the end of memory
+    pub(crate) fn next_opcode(&self) -> u16 {
+        let pc = self.pc as usize;
+        match (self.memory.get(pc), self.memory.get(pc + 1)) {
+            (Some(hi), Some(lo)) => (*hi as u16) << 8 | *lo as u16,
+            _ => 0,
+        }
+    }
+
+    /// Count the delay and sound timers down by one. Hosts call this
+    /// `TIMER_HZ` times per second of emulated time, independently of how
+    /// many instructions they execute in between
+    pub fn tick_timers(&mut self) {
+        self.ticks += 1;
+        self.frame_pos = 0.0;
+        let now = self.emulated_time();
+        self.beep.advance_to(now);
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+        self.update_beeper();
+        self.vblank_wait = false;
+    }
+
+    /// Seconds since the machine started, counted in timer ticks
+    pub fn emulated_time(&self) -> f64 {
+        (self.ticks as f64 + self.frame_pos) / TIMER_HZ as f64
+    }
+
+    fn set_pattern(&mut self) {
+        let now = self.emulated_time();
+        self.beep.advance_to(now);
+        self.beep.set_pattern(&self.pattern, pattern_rate(self.pitch));
+    }
+
+    /// Turn the beeper on or off to match the sound timer
+    fn update_beeper(&mut self) {
+        let on = self.sound_timer > 0;
+        if on != self.beeping {
+            let now = self.emulated_time();
+            self.beep.advance_to(now);
+            if on {
                 self.beep.beep_on();
-                self.sound_timer -= 1;
+            } else {
+                self.beep.beep_off();
             }
-            self.time = now;
+            self.beeping = on;
+        }
+    }
 
-        } else if now.duration_since(self.time) > Duration::from_millis(250) {
-            self.beep.beep_off();
+    /// Register shifted by `8XY6`/`8XYE`, depending on the shift quirk
+    fn shift_source(&self, regx: usize, regy: usize) -> u8 {
+        if self.quirks.shift {
+            self.register[regx]
+        } else {
+            self.register[regy]
         }
+    }
 
-        let code = self.if_();
-        let op = self.id(code);
-        self.ex(op);
+    /// The logic ops clear VF under the vf reset quirk
+    fn vf_reset(&mut self) {
+        if self.quirks.vf_reset {
+            self.register[VF] = 0;
+        }
+    }
 
-        !self.halt
+    fn if_(&mut self) -> Result<u16, Chip8Error> {
+        let pc = self.pc as usize;
+        if pc + 1 >= self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfRange {
+                address: pc + 1,
+                pc: self.pc,
+            });
+        }
+        let code = (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(code)
     }
 
-    fn if_(&mut self) -> u16 {
-        let code = (self.memory[self.pc as usize] as u16) << 8 |
-            (self.memory[(self.pc + 1) as usize]) as u16;
-        self.pc += 2;
-        code
+    /// Skip the next instruction, which is four bytes long if it is
+    /// XO-CHIP's `F000 NNNN`
+    fn skip(&mut self) {
+        let pc = self.pc as usize;
+        let long = self.memory.get(pc) == Some(&0xF0) && self.memory.get(pc + 1) == Some(&0x00);
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
     }
 
-    /// Given a two byte opcode as u16, decode it and return the associated
-    /// opcode enum
-    fn id(&self, code: u16) -> Opcode {
-        let address: u16 = opcode_addr(code);
-        let nibble4: u8 = opcode_nibble4(code);
-        let byte2: u8 = opcode_byte2(code);
+    /// Read a byte on behalf of the instruction currently executing
+    fn read(&mut self, address: usize) -> Result<u8, Chip8Error> {
+        if let Some(ref mut log) = self.accesses {
+            log.push(Access::Read(address));
+        }
+        match self.memory.get(address) {
+            Some(b) => Ok(*b),
+            None => Err(self.out_of_range(address)),
+        }
+    }
 
-        match opcode_n1(code) {
-            0 => {
-                match address {
-                    0x0E0 => Opcode::Clr,
-                    0x0EE => Opcode::Ret,
-                    _ => Opcode::Sys(address),
-                }
-            }
-            1 => Opcode::GoTo(address),
-            2 => Opcode::Call(address),
-            3 => Opcode::SkipEq((opcode_regx(code), byte2)),
-            4 => Opcode::SkipNeq((opcode_regx(code), byte2)),
-            5 => {
-                if nibble4 != 0 {
-                    Opcode::Nope(code)
-                } else {
-                    Opcode::SkipEqR((opcode_regx(code), opcode_regy(code)))
-                }
-            }
-            6 => Opcode::SetR((opcode_regx(code), byte2)),
-            7 => Opcode::AddR((opcode_regx(code), byte2)),
-            8 => {
-                match nibble4 {
-                    0 => Opcode::AssignR((opcode_regx(code), opcode_regy(code))),
-                    1 => Opcode::OrR((opcode_regx(code), opcode_regy(code))),
-                    2 => Opcode::AndR((opcode_regx(code), opcode_regy(code))),
-                    3 => Opcode::XorR((opcode_regx(code), opcode_regy(code))),
-                    4 => Opcode::AddR2((opcode_regx(code), opcode_regy(code))),
-                    5 => Opcode::SubR((opcode_regx(code), opcode_regy(code))),
-                    6 => Opcode::RShiftR((opcode_regx(code), opcode_regy(code))),
-                    7 => Opcode::SubR2((opcode_regx(code), opcode_regy(code))),
-                    0xE => Opcode::LShiftR((opcode_regx(code), opcode_regy(code))),
-                    _ => Opcode::Nope(code),
-                }
-            }
-            9 => Opcode::SkipNeqR((opcode_regx(code), opcode_regy(code))),
-            0xA => Opcode::Mem(address),
-            0xB => Opcode::Jmp(address),
-            0xC => Opcode::Rand((opcode_regx(code), byte2)),
-            0xD => Opcode::Disp((opcode_regx(code), opcode_regy(code), nibble4)),
-            0xE => {
-                match byte2 {
-                    0x9E => Opcode::KeyPress(opcode_regx(code)),
-                    0xA1 => Opcode::KeyNoPress(opcode_regx(code)),
-                    _ => Opcode::Nope(code),
-                }
-            }
-            0xF => {
-                match byte2 {
-                    0x07 => Opcode::GetDelay(opcode_regx(code)),
-                    0x0A => Opcode::WaitKey(opcode_regx(code)),
-                    0x15 => Opcode::SetDelay(opcode_regx(code)),
-                    0x18 => Opcode::SetSound(opcode_regx(code)),
-                    0x1E => Opcode::AddM(opcode_regx(code)),
-                    0x29 => Opcode::Sprite(opcode_regx(code)),
-                    0x33 => Opcode::Bcd(opcode_regx(code)),
-                    0x55 => Opcode::DumpR(opcode_regx(code)),
-                    0x65 => Opcode::LoadR(opcode_regx(code)),
-                    _ => Opcode::Nope(code),
-                }
-            }
-            _ => Opcode::Nope(code),
+    /// Write a byte on behalf of the instruction currently executing
+    fn write(&mut self, address: usize, val: u8) -> Result<(), Chip8Error> {
+        if address >= self.memory.len() {
+            return Err(self.out_of_range(address));
+        }
+        if let Some(ref mut log) = self.accesses {
+            log.push(Access::Write(address));
+        }
+        self.memory[address] = val;
+        Ok(())
+    }
+
+    fn out_of_range(&self, address: usize) -> Chip8Error {
+        Chip8Error::MemoryOutOfRange {
+            address,
+            pc: self.pc.wrapping_sub(2),
         }
     }
 
-    fn ex(&mut self, code: Opcode) {
+    fn ex(&mut self, code: Instruction) -> Result<(), Chip8Error> {
         match code {
-            Opcode::Nope(bytes) => {
-                let byte1: u8 = (bytes >> 8) as u8;
-                let byte2: u8 = opcode_byte2(bytes);
-                panic!(
-                    "Invalid instruction '{} {}' at PC '{}'",
-                    format!("{:#X}", byte1),
-                    format!("{:#X}", byte2),
-                    format!("{:#X}", self.pc - 2)
-                )
+            Instruction::Nope(bytes) => {
+                return Err(Chip8Error::InvalidOpcode {
+                    opcode: bytes,
+                    address: self.pc.wrapping_sub(2),
+                })
             }
-            Opcode::Sys(_) => {
+            Instruction::Sys(_) => {
                 // valid instruction, but noop it
-                ()
             }
-            Opcode::Clr => {
-                self.grid = [false; 32 * 64];
-                self.display.clear()
+            Instruction::Clr => {
+                self.grid.clear(self.plane);
+                self.display.clear();
+                self.redraw()?
             }
-            Opcode::Ret => {
+            Instruction::Ret => {
                 match self.stack.pop() {
                     None => {
-                        // XXX herp derp. Critical error?
-                        panic!("Attempted to return, but no subroutine. you suck at coding")
+                        return Err(Chip8Error::StackUnderflow {
+                            address: self.pc.wrapping_sub(2),
+                        })
                     }
                     Some(n) => self.pc = n,
                 }
             }
-            Opcode::GoTo(address) => self.pc = address,
-            Opcode::Call(address) => {
+            Instruction::ScrollDown(n) => {
+                self.grid.scroll_down(n as usize, self.plane);
+                self.redraw()?
+            }
+            Instruction::ScrollUp(n) => {
+                self.grid.scroll_up(n as usize, self.plane);
+                self.redraw()?
+            }
+            Instruction::ScrollRight => {
+                self.grid.scroll_right(4, self.plane);
+                self.redraw()?
+            }
+            Instruction::ScrollLeft => {
+                self.grid.scroll_left(4, self.plane);
+                self.redraw()?
+            }
+            Instruction::Exit => self.halt = true,
+            Instruction::LoRes => {
+                self.grid.set_hires(false);
+                self.display.clear();
+                self.redraw()?
+            }
+            Instruction::HiRes => {
+                self.grid.set_hires(true);
+                self.display.clear();
+                self.redraw()?
+            }
+            Instruction::GoTo(address) => self.pc = address,
+            Instruction::Call(address) => {
                 if self.stack.len() == 24 {
-                    // XXX herp derp. Critical error?
-                    panic!("Attempted to call, but stack is full. you suck at coding")
+                    return Err(Chip8Error::StackOverflow { address: self.pc.wrapping_sub(2) });
                 }
                 self.stack.push(self.pc);
                 self.pc = address
             }
-            Opcode::SkipEq((regx, val)) => {
+            Instruction::SkipEq((regx, val)) => {
                 if self.register[regx] == val {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
-            Opcode::SkipNeq((regx, val)) => {
+            Instruction::SkipNeq((regx, val)) => {
                 if self.register[regx] != val {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
-            Opcode::SkipEqR((regx, regy)) => {
+            Instruction::SkipEqR((regx, regy)) => {
                 if self.register[regx] == self.register[regy] {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
-            Opcode::SetR((regx, val)) => self.register[regx] = val,
-            Opcode::AddR((regx, val)) => {
+            Instruction::SetR((regx, val)) => self.register[regx] = val,
+            Instruction::AddR((regx, val)) => {
                 let (val, _) = self.register[regx].overflowing_add(val);
                 self.register[regx] = val
             }
 
             // 8XXX
-            Opcode::AssignR((regx, regy)) => self.register[regx] = self.register[regy],
-            Opcode::OrR((regx, regy)) => self.register[regx] |= self.register[regy],
-            Opcode::AndR((regx, regy)) => self.register[regx] &= self.register[regy],
-            Opcode::XorR((regx, regy)) => self.register[regx] ^= self.register[regy],
-            Opcode::AddR2((regx, regy)) => {
+            Instruction::AssignR((regx, regy)) => self.register[regx] = self.register[regy],
+            Instruction::OrR((regx, regy)) => {
+                self.register[regx] |= self.register[regy];
+                self.vf_reset()
+            }
+            Instruction::AndR((regx, regy)) => {
+                self.register[regx] &= self.register[regy];
+                self.vf_reset()
+            }
+            Instruction::XorR((regx, regy)) => {
+                self.register[regx] ^= self.register[regy];
+                self.vf_reset()
+            }
+            Instruction::AddR2((regx, regy)) => {
                 let (val, overflow) = self.register[regx].overflowing_add(self.register[regy]);
                 if overflow {
                     self.register[VF] = 1;
@@ -330,7 +807,7 @@ impl<D: Display, I: Input, B: Beeper> Chip8<D, I, B> {
                 }
                 self.register[regx] = val
             }
-            Opcode::SubR((regx, regy)) => {
+            Instruction::SubR((regx, regy)) => {
                 let rx = self.register[regx];
                 let ry = self.register[regy];
                 if ry > rx {
@@ -342,15 +819,12 @@ impl<D: Display, I: Input, B: Beeper> Chip8<D, I, B> {
                 self.register[regx] = val;
                 self.register[regx] = val
             }
-            Opcode::RShiftR((regx, regy)) => {
-                let ry = self.register[regy];
-                let vf = ry << 7 >> 7;
-                let r = ry >> 1;
-                self.register[regy] = r;
-                self.register[regx] = r;
-                self.register[VF] = vf
+            Instruction::RShiftR((regx, regy)) => {
+                let src = self.shift_source(regx, regy);
+                self.register[regx] = src >> 1;
+                self.register[VF] = src & 1
             }
-            Opcode::SubR2((regx, regy)) => {
+            Instruction::SubR2((regx, regy)) => {
                 let rx = self.register[regx];
                 let ry = self.register[regy];
                 if ry > rx {
@@ -361,102 +835,140 @@ impl<D: Display, I: Input, B: Beeper> Chip8<D, I, B> {
                 let (val, _) = ry.overflowing_sub(rx);
                 self.register[regx] = val
             }
-            Opcode::LShiftR((regx, regy)) => {
-                let ry = self.register[regy];
-                let vf = ry >> 7;
-                let r = ry << 1;
-                self.register[regy] = r;
-                self.register[regx] = r;
-                self.register[VF] = vf
+            Instruction::LShiftR((regx, regy)) => {
+                let src = self.shift_source(regx, regy);
+                self.register[regx] = src << 1;
+                self.register[VF] = src >> 7
             }
 
             // 9XXX
-            Opcode::SkipNeqR((regx, regy)) => {
+            Instruction::SkipNeqR((regx, regy)) => {
                 if self.register[regx] != self.register[regy] {
-                    self.pc += 2
+                    self.skip()
                 }
             }
 
             // ANNN
-            Opcode::Mem(address) => self.address_reg = address,
+            Instruction::Mem(address) => self.address_reg = address,
 
             // BNNN
-            Opcode::Jmp(address) => self.pc = address + self.register[V0] as u16,
+            Instruction::Jmp(address) => {
+                let reg = if self.quirks.jump {
+                    opcode_regx(address)
+                } else {
+                    V0
+                };
+                self.pc = address + self.register[reg] as u16
+            }
 
             // CXNN
-            Opcode::Rand((regx, val)) => {
-                let rand = self.rng.gen::<u8>();
+            Instruction::Rand((regx, val)) => {
+                let rand = self.rng.next_u8();
                 self.register[regx] = rand & val
             }
 
             // DXYN
-            Opcode::Disp((regx, regy, h)) => {
-                let x = self.register[regx];
-                let y = self.register[regy];
+            Instruction::Disp((regx, regy, n)) => {
+                let (w, h) = (self.grid.width(), self.grid.height());
+                // The starting position always wraps; the quirk decides what
+                // happens to the rest of the sprite
+                let x = self.register[regx] as usize % w;
+                let y = self.register[regy] as usize % h;
+
+                // DXY0 is SUPER-CHIP's 16x16 sprite, two bytes per row
+                let (sw, sh) = if n == 0 { (16, 16) } else { (8, n as usize) };
+                let stride = sw / 8;
 
                 let mut vf = 0;
-                // Took this from https://github.com/JamesGriffin/CHIP-8-Emulator/blob/master/src/chip8.cpp
-                for yline in 0..h {
-                    let i = self.address_reg as usize + yline as usize;
-                    let pixel = self.memory[i];
-
-                    for xline in 0..8 {
-                        if (pixel & (0x80 >> xline)) != 0 {
-                            let gi = (y + yline) as usize * 64;
-                            let g = (x + xline) as usize + gi as usize;
-                            if self.grid[g] {
-                                vf = 1;
-                            }
+                // Each selected XO-CHIP plane takes the next sprite's worth
+                // of data
+                let mut i = self.address_reg as usize;
+                for plane in 0..PLANES {
+                    let bit = 1 << plane;
+                    if self.plane & bit == 0 {
+                        continue;
+                    }
 
-                            self.grid[g] ^= true;
+                    // Took this from https://github.com/JamesGriffin/CHIP-8-Emulator/blob/master/src/chip8.cpp
+                    for yline in 0..sh {
+                        let mut pixel: u16 = 0;
+                        for b in 0..stride {
+                            pixel = pixel << 8 | self.read(i + yline * stride + b)? as u16;
+                        }
+
+                        for xline in 0..sw {
+                            if (pixel & (1 << (sw - 1 - xline))) != 0 {
+                                let (px, py) = (x + xline, y + yline);
+                                if self.quirks.clip && (px >= w || py >= h) {
+                                    continue;
+                                }
+                                if self.grid.toggle(px % w, py % h, bit) {
+                                    vf = 1;
+                                }
+                            }
                         }
                     }
+                    // end James Griffin's algorithm
+                    i += sh * stride;
                 }
-                // end James Griffin's algorithm
 
+                self.grid.mark(x, y, sw, sh, !self.quirks.clip);
                 self.register[VF] = vf;
-                match self.display.draw(&self.grid) {
-                    Ok(_) => (),
-                    Err(e) => panic!(e),
-                }
+                self.vblank_wait = self.quirks.display_wait;
+                self.redraw()?
             }
 
             // EXXX
-            Opcode::KeyPress(regx) => {
-                if self.input.key(self.register[regx]) {
-                    self.pc += 2
+            Instruction::KeyPress(regx) => {
+                if self.key_down(self.register[regx]) {
+                    self.skip()
                 }
             }
-            Opcode::KeyNoPress(regx) => {
-                if !self.input.key(self.register[regx]) {
-                    self.pc += 2
+            Instruction::KeyNoPress(regx) => {
+                if !self.key_down(self.register[regx]) {
+                    self.skip()
                 }
             }
 
             // FXXX
-            Opcode::GetDelay(regx) => self.register[regx] = self.delay_timer,
-            Opcode::WaitKey(regx) => {
-                match self.input.block_for() {
-                    None => self.halt = true,
-                    Some(x) => self.register[regx] = x,
+            Instruction::GetDelay(regx) => self.register[regx] = self.delay_timer,
+            Instruction::WaitKey(regx) => {
+                // Like the VIP, take the key once it is let go. Until then
+                // the instruction repeats, so timers keep running
+                let keys = self.input.keys();
+                match self.key {
+                    Some(k) if !self.key_down(k) => {
+                        self.register[regx] = k;
+                        self.key = None;
+                    }
+                    Some(_) => self.pc = self.pc.wrapping_sub(2),
+                    None => {
+                        if keys != 0 {
+                            self.key = Some(keys.trailing_zeros() as u8);
+                        }
+                        self.pc = self.pc.wrapping_sub(2);
+                    }
                 }
             }
-            Opcode::SetDelay(regx) => self.delay_timer = self.register[regx],
-            Opcode::SetSound(regx) => self.sound_timer = self.register[regx],
-            Opcode::AddM(regx) => {
-                // Undocumented darkness
-                let (val, overflow) = self.address_reg.overflowing_add(self.register[regx] as u16);
-                if overflow {
-                    self.register[VF] = 1;
-                } else {
-                    self.register[VF] = 0;
+            Instruction::SetDelay(regx) => self.delay_timer = self.register[regx],
+            Instruction::SetSound(regx) => {
+                self.sound_timer = self.register[regx];
+                self.update_beeper()
+            }
+            Instruction::AddM(regx) => {
+                self.address_reg = self.address_reg.wrapping_add(self.register[regx] as u16);
+                if self.quirks.index_overflow {
+                    self.register[VF] = (self.address_reg > 0xFFF) as u8;
                 }
-                self.address_reg = val
             }
-            Opcode::Sprite(regx) => {
+            Instruction::Sprite(regx) => {
                 self.address_reg = self.register[regx] as u16 * 5;
             }
-            Opcode::Bcd(regx) => {
+            Instruction::BigSprite(regx) => {
+                let digit = self.register[regx] as usize & 0xF;
+                self.address_reg = (BIG_FONT_ADDR + digit * 10) as u16;
+            }
+            Instruction::Bcd(regx) => {
                 let mut rx = self.register[regx];
                 let hundreds: u8 = rx / 100;
                 rx %= 100;
@@ -464,144 +976,91 @@ impl<D: Display, I: Input, B: Beeper> Chip8<D, I, B> {
                 rx %= 10;
                 let ones: u8 = rx;
 
-                self.memory[self.address_reg as usize] = hundreds;
-                self.memory[self.address_reg as usize + 1] = tens;
-                self.memory[self.address_reg as usize + 2] = ones
+                let i = self.address_reg as usize;
+                self.write(i, hundreds)?;
+                self.write(i + 1, tens)?;
+                self.write(i + 2, ones)?
             }
-            Opcode::DumpR(regx) => {
+            Instruction::DumpR(regx) => {
                 let num: usize = regx + 1;
                 for x in 0..num {
-                    self.memory[self.address_reg as usize + x] = self.register[x];
+                    let val = self.register[x];
+                    self.write(self.address_reg as usize + x, val)?;
+                }
+                if !self.quirks.load_store {
+                    self.address_reg = self.address_reg.wrapping_add(num as u16)
                 }
-                self.address_reg += num as u16
             }
-            Opcode::LoadR(regx) => {
+            Instruction::LoadR(regx) => {
                 let num: usize = regx + 1;
                 for x in 0..num {
-                    self.register[x] = self.memory[self.address_reg as usize + x]
+                    self.register[x] = self.read(self.address_reg as usize + x)?
+                }
+                if !self.quirks.load_store {
+                    self.address_reg = self.address_reg.wrapping_add(num as u16)
                 }
-                self.address_reg += num as u16
+            }
+            Instruction::LongMem => {
+                let pc = self.pc as usize;
+                let address = (self.read(pc)? as u16) << 8 | self.read(pc + 1)? as u16;
+                self.address_reg = address;
+                self.pc = self.pc.wrapping_add(2)
+            }
+            Instruction::SaveRange((regx, regy)) => {
+                for (n, r) in register_range(regx, regy).into_iter().enumerate() {
+                    let val = self.register[r];
+                    self.write(self.address_reg as usize + n, val)?;
+                }
+            }
+            Instruction::LoadRange((regx, regy)) => {
+                for (n, r) in register_range(regx, regy).into_iter().enumerate() {
+                    self.register[r] = self.read(self.address_reg as usize + n)?;
+                }
+            }
+            Instruction::Plane(mask) => self.plane = mask,
+            Instruction::Audio => {
+                for n in 0..16 {
+                    self.pattern[n] = self.read(self.address_reg as usize + n)?;
+                }
+                self.set_pattern()
+            }
+            Instruction::Pitch(regx) => {
+                self.pitch = self.register[regx];
+                self.set_pattern()
+            }
+            Instruction::SaveFlags(regx) => {
+                self.rpl[..regx + 1].copy_from_slice(&self.register[..regx + 1])
+            }
+            Instruction::LoadFlags(regx) => {
+                self.register[..regx + 1].copy_from_slice(&self.rpl[..regx + 1])
             }
         }
+        Ok(())
     }
-}
-
-#[allow(dead_code)]
-mod data {
-    pub type Unknown = (u16);
-    pub type Address = (u16);
-    pub type Register = (usize);
-    pub type Registers = (usize, usize);
-    pub type RegisterAndValue = (usize, u8);
-    pub type RegistersAndValue = (usize, usize, u8);
-}
 
-enum Opcode {
-    /// invalid opcode
-    Nope(data::Unknown),
-
-    /// 0XXX
-    Sys(data::Address),
-    Clr,
-    Ret,
-
-    /// 1XXX
-    GoTo(data::Address),
-
-    /// 2XXX
-    Call(data::Address),
-
-    /// 3XXX
-    SkipEq(data::RegisterAndValue),
-
-    /// 4XXX
-    SkipNeq(data::RegisterAndValue),
-
-    /// 5XXX
-    SkipEqR(data::Registers),
-
-    /// 6XXX
-    SetR(data::RegisterAndValue),
-
-    /// 7XXX
-    AddR(data::RegisterAndValue),
-
-    /// 8XXX
-    AssignR(data::Registers),
-    OrR(data::Registers),
-    AndR(data::Registers),
-    XorR(data::Registers),
-    AddR2(data::Registers),
-    SubR(data::Registers),
-    RShiftR(data::Registers),
-    SubR2(data::Registers),
-    LShiftR(data::Registers),
-
-    /// 9XXX
-    SkipNeqR(data::Registers),
-
-    /// AXXX
-    Mem(data::Address),
-
-    /// BXXX
-    Jmp(data::Address),
-
-    /// CXXX
-    Rand(data::RegisterAndValue),
-
-    /// DXXX
-    Disp(data::RegistersAndValue),
-
-    /// EXXX
-    KeyPress(data::Register),
-    KeyNoPress(data::Register),
-
-    /// FXXX
-    GetDelay(data::Register),
-    WaitKey(data::Register),
-    SetDelay(data::Register),
-    SetSound(data::Register),
-    AddM(data::Register),
-    Sprite(data::Register),
-    Bcd(data::Register),
-    DumpR(data::Register),
-    LoadR(data::Register),
-}
-
-#[inline]
-/// Return the most significant nibble
-fn opcode_n1(code: u16) -> u8 {
-    (code >> 12) as u8
-}
-
-#[inline]
-/// return the least significant 12 bits
-fn opcode_addr(code: u16) -> u16 {
-    code << 4 >> 4
-}
-
-#[inline]
-/// Return the second nibble
-fn opcode_regx(code: u16) -> usize {
-    (code << 4 >> 12) as usize
-}
-
-#[inline]
-/// Return the third nibble
-fn opcode_regy(code: u16) -> usize {
-    (code << 8 >> 12) as usize
+    /// Hand the whole screen to the display
+    fn redraw(&mut self) -> Result<(), Chip8Error> {
+        self.display_dirty = true;
+        if self.in_frame {
+            return Ok(());
+        }
+        self.display.draw(&self.grid).map_err(Chip8Error::Display)
+    }
 }
 
-#[inline]
-/// return the forth nibble
-fn opcode_nibble4(code: u16) -> u8 {
-    (code << 12 >> 12) as u8
+/// Registers X through Y for `5XY2`/`5XY3`, counting down if X > Y
+fn register_range(regx: usize, regy: usize) -> Vec<usize> {
+    if regx <= regy {
+        (regx..regy + 1).collect()
+    } else {
+        (regy..regx + 1).rev().collect()
+    }
 }
 
-#[inline]
-/// Return the second byte
-fn opcode_byte2(code: u16) -> u8 {
-    (code << 8 >> 8) as u8
+/// XO-CHIP audio pattern playback rate, in samples per second, for a pitch
+/// register value; 64 is 4000 Hz
+fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
 }
 
 #[cfg(test)]
@@ -0,0 +1,154 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Interpretations of the instructions that CHIP-8 implementations disagree
+/// on. Each flag's doc says what setting it does. No platform leaves them
+/// all unset; `cosmac_vip()`, `chip48()`, `superchip()` and `xo_chip()` give
+/// the combination each platform used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VX in place and ignore VY, instead of shifting VY
+    /// into VX
+    pub shift: bool,
+
+    /// `FX55`/`FX65` leave I unchanged, instead of incrementing it past the
+    /// last register stored or loaded
+    pub load_store: bool,
+
+    /// `BNNN` is read as `BXNN` and jumps to XNN + VX, instead of NNN + V0
+    pub jump: bool,
+
+    /// `8XY1`/`8XY2`/`8XY3` reset VF to 0
+    pub vf_reset: bool,
+
+    /// Sprites are clipped at the edges of the screen instead of wrapping
+    /// around to the other side
+    pub clip: bool,
+
+    /// `DXYN` waits for the next timer tick (vertical blank) before the CPU
+    /// continues, limiting programs to one sprite per frame
+    pub display_wait: bool,
+
+    /// XO-CHIP's 64 KiB address space instead of 4 KiB
+    pub extended_memory: bool,
+
+    /// `FX1E` sets VF to 1 when I goes past 0xFFF and to 0 otherwise, as the
+    /// Amiga interpreter did, instead of leaving VF alone
+    pub index_overflow: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift: false,
+            load_store: false,
+            jump: false,
+            vf_reset: true,
+            clip: true,
+            display_wait: true,
+            extended_memory: false,
+            index_overflow: false,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators
+    pub fn chip48() -> Self {
+        Quirks {
+            shift: true,
+            load_store: true,
+            jump: true,
+            vf_reset: false,
+            clip: true,
+            display_wait: false,
+            extended_memory: false,
+            index_overflow: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1, which kept CHIP-48's interpretations
+    pub fn superchip() -> Self {
+        Quirks::chip48()
+    }
+
+    /// XO-CHIP, as implemented by Octo
+    pub fn xo_chip() -> Self {
+        Quirks {
+            shift: false,
+            load_store: false,
+            jump: false,
+            vf_reset: false,
+            clip: false,
+            display_wait: false,
+            extended_memory: true,
+            index_overflow: false,
+        }
+    }
+
+    /// Every flag, by the name of its field
+    pub fn flags(&self) -> [(&'static str, bool); 8] {
+        [
+            ("shift", self.shift),
+            ("load_store", self.load_store),
+            ("jump", self.jump),
+            ("vf_reset", self.vf_reset),
+            ("clip", self.clip),
+            ("display_wait", self.display_wait),
+            ("extended_memory", self.extended_memory),
+            ("index_overflow", self.index_overflow),
+        ]
+    }
+
+    /// Set the flag named as in `flags`. Returns false if there is no such
+    /// flag
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        let flag = match name {
+            "shift" => &mut self.shift,
+            "load_store" => &mut self.load_store,
+            "jump" => &mut self.jump,
+            "vf_reset" => &mut self.vf_reset,
+            "clip" => &mut self.clip,
+            "display_wait" => &mut self.display_wait,
+            "extended_memory" => &mut self.extended_memory,
+            "index_overflow" => &mut self.index_overflow,
+            _ => return false,
+        };
+        *flag = value;
+        true
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::cosmac_vip()
+    }
+}
+
+/// Returned when parsing the name of an unknown quirks preset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPreset(pub String);
+
+impl fmt::Display for UnknownPreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unknown quirks preset '{}', expected one of vip, chip48, schip, xochip",
+            self.0
+        )
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = UnknownPreset;
+
+    /// Look up a preset by name: `vip`, `chip48`, `schip` or `xochip`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "vip" | "chip8" | "cosmac" => Ok(Quirks::cosmac_vip()),
+            "chip48" => Ok(Quirks::chip48()),
+            "schip" | "superchip" => Ok(Quirks::superchip()),
+            "xochip" | "xo-chip" => Ok(Quirks::xo_chip()),
+            _ => Err(UnknownPreset(s.to_string())),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Recordings of the screen for gameplay clips, sampled once per frame
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use framebuffer::{Framebuffer, HIRES_HEIGHT, HIRES_WIDTH};
+use image::{self, Gif};
+use TIMER_HZ;
+
+/// GIF viewers play anything quicker than this, in hundredths of a second,
+/// as slowly as they like
+const MIN_GIF_DELAY: u64 = 2;
+
+enum Sink {
+    /// The frame not yet written, as it may show for longer, and the time
+    /// written so far in hundredths of a second
+    Gif {
+        gif: Gif<BufWriter<File>>,
+        pending: Option<Vec<u8>>,
+        written: u64,
+    },
+    /// A PNG per frame, numbered from 0, in this directory
+    Png(PathBuf),
+}
+
+/// Records the screen as an animated GIF or a numbered PNG sequence, given
+/// it by `frame` once per emulated frame.
+///
+/// Every frame has the size of the hi-res screen times `scale`, with lo-res
+/// pixels twice as big, so switching resolution keeps the size. The GIF
+/// only stores frames that differ from the one before, showing each until
+/// the next, but keeps to GIF's 50 frames per second; PNG sequences have
+/// every frame, for video tools that expect a steady rate
+pub struct Recorder {
+    sink: Sink,
+    palette: [(u8, u8, u8); 16],
+    scale: usize,
+
+    /// Frames recorded so far
+    frames: u64,
+}
+
+impl Recorder {
+    /// Record to `path`: a GIF if it ends in `.gif`, otherwise a directory
+    /// of PNGs, which is created if needed
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        palette: &[(u8, u8, u8); 16],
+        scale: usize,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        let gif = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+        let sink = if gif {
+            let (width, height) = (HIRES_WIDTH * scale, HIRES_HEIGHT * scale);
+            if width > u16::MAX as usize || height > u16::MAX as usize {
+                let message = "Scale too large for a GIF";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+            let file = BufWriter::new(File::create(path)?);
+            Sink::Gif {
+                gif: Gif::new(file, width as u16, height as u16, palette)?,
+                pending: None,
+                written: 0,
+            }
+        } else {
+            fs::create_dir_all(path)?;
+            Sink::Png(path.to_path_buf())
+        };
+        Ok(Recorder {
+            sink,
+            palette: *palette,
+            scale,
+            frames: 0,
+        })
+    }
+
+    /// Frames recorded so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Record the screen as it is at the end of a frame
+    pub fn frame(&mut self, grid: &Framebuffer) -> io::Result<()> {
+        let pixels = self.canvas(grid);
+        let now = centiseconds(self.frames);
+        self.frames += 1;
+        match self.sink {
+            Sink::Gif { ref mut gif, ref mut pending, ref mut written } => {
+                match pending.take() {
+                    Some(ref last) if *last == pixels => *pending = Some(pixels),
+                    // Too short to show; the new frame takes its place
+                    Some(_) if now - *written < MIN_GIF_DELAY => *pending = Some(pixels),
+                    Some(last) => {
+                        gif.frame(&last, delay(now - *written))?;
+                        *written = now;
+                        *pending = Some(pixels);
+                    }
+                    None => *pending = Some(pixels),
+                }
+                Ok(())
+            }
+            Sink::Png(ref dir) => {
+                let (width, height) = (HIRES_WIDTH * self.scale, HIRES_HEIGHT * self.scale);
+                let png = image::indexed_png(width, height, &pixels, &self.palette);
+                fs::write(dir.join(format!("{:06}.png", self.frames - 1)), png)
+            }
+        }
+    }
+
+    /// Write out the last frame and end the file
+    pub fn finish(self) -> io::Result<()> {
+        match self.sink {
+            Sink::Gif { mut gif, pending, written } => {
+                if let Some(last) = pending {
+                    let shown = (centiseconds(self.frames) - written).max(MIN_GIF_DELAY);
+                    gif.frame(&last, delay(shown))?;
+                }
+                gif.finish().map(|_| ())
+            }
+            Sink::Png(_) => Ok(()),
+        }
+    }
+
+    /// The palette index of every pixel of a frame
+    fn canvas(&self, grid: &Framebuffer) -> Vec<u8> {
+        let (width, height) = (HIRES_WIDTH * self.scale, HIRES_HEIGHT * self.scale);
+        let size = self.scale * HIRES_WIDTH / grid.width();
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(grid.get(x / size, y / size) & 0xF);
+            }
+        }
+        pixels
+    }
+}
+
+/// The time `frames` frames take, in hundredths of a second
+fn centiseconds(frames: u64) -> u64 {
+    frames * 100 / TIMER_HZ as u64
+}
+
+/// A GIF frame delay, as near to `centiseconds` as it can be
+fn delay(centiseconds: u64) -> u16 {
+    centiseconds.min(u16::MAX as u64) as u16
+}
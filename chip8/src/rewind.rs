@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+
+use super::{Beeper, Chip8, Display, Input};
+use state::{compress, decompress};
+
+/// A bounded history of recent machine states to step backwards through.
+///
+/// Frontends call `record` once per frame; every `interval` frames it takes a
+/// snapshot. Only the newest snapshot is kept whole. Each older one is stored
+/// as the zero run-length encoded XOR against the snapshot after it, which is
+/// mostly zeros since little changes in a few frames. Once `capacity`
+/// snapshots are held the oldest is dropped
+pub struct Rewind {
+    capacity: usize,
+    interval: u32,
+    countdown: u32,
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` snapshots, one every `interval` frames
+    pub fn new(capacity: usize, interval: u32) -> Self {
+        Rewind {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            countdown: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Number of snapshots that can currently be rewound to
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Forget all history, for instance after loading another ROM
+    pub fn clear(&mut self) {
+        self.countdown = 0;
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    /// Called once per frame; snapshots `c` when the interval has elapsed
+    pub fn record<D: Display, I: Input, B: Beeper>(&mut self, c: &Chip8<D, I, B>) {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return;
+        }
+        self.countdown = self.interval - 1;
+
+        let snapshot = c.snapshot();
+        if let Some(older) = self.newest.take() {
+            self.deltas.push_back(delta(&older, &snapshot));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.newest = Some(snapshot);
+    }
+
+    /// Restore the newest snapshot into `c` and drop it, so the next call
+    /// goes further back. Returns false once the history is exhausted
+    pub fn rewind<D: Display, I: Input, B: Beeper>(&mut self, c: &mut Chip8<D, I, B>) -> bool {
+        let newest = match self.newest.take() {
+            Some(s) => s,
+            None => return false,
+        };
+        self.newest = self.deltas.pop_back().map(|d| undelta(&newest, &d));
+        self.countdown = self.interval - 1;
+        c.restore(&newest).is_ok()
+    }
+}
+
+/// Encode `older` relative to `newer`: its length, then the compressed XOR
+/// of the two, padding the shorter with zeros
+fn delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let len = older.len().max(newer.len());
+    let xor: Vec<u8> = (0..len)
+        .map(|i| older.get(i).unwrap_or(&0) ^ newer.get(i).unwrap_or(&0))
+        .collect();
+    let mut out = Vec::new();
+    out.extend_from_slice(&[
+        (older.len() >> 24) as u8,
+        (older.len() >> 16) as u8,
+        (older.len() >> 8) as u8,
+        older.len() as u8,
+    ]);
+    compress(&xor, &mut out);
+    out
+}
+
+/// Rebuild the snapshot `delta` was taken from
+fn undelta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let len = delta[..4].iter().fold(0, |v, b| v << 8 | *b as usize);
+    // Only ever decoding our own output, which is well formed
+    let mut older = decompress(&delta[4..]).unwrap_or_default();
+    for (o, n) in older.iter_mut().zip(newer) {
+        *o ^= *n;
+    }
+    older.truncate(len);
+    older
+}
@@ -0,0 +1,46 @@
+/// The random number generator behind `CXNN`. It is a plain xorshift64*, so
+/// two machines seeded alike produce the same numbers, and its whole state
+/// is one `u64`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Self {
+        // Run the seed through SplitMix64 so that small seeds still give a
+        // well mixed, and never zero, starting state
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Xorshift {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    /// Rebuild a generator from `state()`
+    pub fn from_state(state: u64) -> Self {
+        Xorshift {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        // The high bits are the best mixed
+        (self.next_u64() >> 56) as u8
+    }
+}
@@ -0,0 +1,327 @@
+//! Synthesis of the beeper's tone, for frontends that produce their own
+//! samples. A `Synth` turns the beeper's on/off gate into samples, with a
+//! short attack and release so the sound does not click on and off.
+//! `WavBeeper` uses one to record the beeper to a file
+
+use std::f32::consts::PI;
+use std::fmt;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+use rng::Xorshift;
+use Beeper;
+
+/// The shape of the tone played while the beeper is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    /// Random levels held for a period each, so the noise has a pitch
+    Noise,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownWaveform(pub String);
+
+impl fmt::Display for UnknownWaveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unknown waveform '{}', expected one of square, triangle, sine, noise",
+            self.0
+        )
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = UnknownWaveform;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sine" => Ok(Waveform::Sine),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(UnknownWaveform(s.to_string())),
+        }
+    }
+}
+
+/// How the beeper sounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+
+    /// In Hz
+    pub pitch: f32,
+
+    /// Peak amplitude, from 0 to 1
+    pub volume: f32,
+
+    /// Seconds taken to fade in when the beeper turns on, and out when it
+    /// turns off
+    pub attack: f32,
+    pub release: f32,
+}
+
+impl Default for Tone {
+    /// A 440 Hz square wave at a fifth of full volume, fading over 5 ms
+    fn default() -> Self {
+        Tone {
+            waveform: Waveform::Square,
+            pitch: 440.0,
+            volume: 0.2,
+            attack: 0.005,
+            release: 0.005,
+        }
+    }
+}
+
+/// Generates the beeper's sound at a fixed sample rate
+#[derive(Debug, Clone)]
+pub struct Synth {
+    tone: Tone,
+    rate: f32,
+    on: bool,
+
+    /// Position within the current period, from 0 to 1
+    phase: f32,
+
+    /// Envelope level, from 0 to 1
+    level: f32,
+
+    /// The level `Waveform::Noise` holds for the current period
+    noise: f32,
+    rng: Xorshift,
+
+    /// XO-CHIP audio pattern; once set, it replaces the waveform
+    pattern: Option<[u8; 16]>,
+    pattern_inc: f32,
+    pattern_pos: f32,
+}
+
+impl Synth {
+    /// A silent synth producing `rate` samples per second
+    pub fn new(tone: Tone, rate: u32) -> Self {
+        Synth {
+            tone,
+            rate: rate as f32,
+            on: false,
+            phase: 0.0,
+            level: 0.0,
+            noise: 0.0,
+            rng: Xorshift::new(0),
+            pattern: None,
+            pattern_inc: 0.0,
+            pattern_pos: 0.0,
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        self.tone
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+    }
+
+    /// Start fading in or out
+    pub fn set_on(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    /// Play `pattern` at `rate` samples per second, as `Beeper::set_pattern`
+    pub fn set_pattern(&mut self, pattern: &[u8; 16], rate: f32) {
+        self.pattern = Some(*pattern);
+        self.pattern_inc = rate / self.rate;
+    }
+
+    /// Whether the beeper is off and has finished fading out
+    pub fn is_silent(&self) -> bool {
+        !self.on && self.level == 0.0
+    }
+
+    pub fn fill(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            *x = self.next_sample();
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        self.step_envelope();
+        if self.level == 0.0 {
+            return 0.0;
+        }
+
+        let wave = match self.pattern {
+            Some(ref pattern) => {
+                // Loop through the pattern's 128 bits
+                let bit = self.pattern_pos as usize;
+                self.pattern_pos = (self.pattern_pos + self.pattern_inc) % 128.0;
+                if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 }
+            }
+            None => self.wave(),
+        };
+        wave * self.tone.volume * self.level
+    }
+
+    /// The waveform at the current phase, advancing it by a sample
+    fn wave(&mut self) -> f32 {
+        let p = self.phase;
+        let wave = match self.tone.waveform {
+            Waveform::Square => if p < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (p - 0.5).abs(),
+            Waveform::Sine => (2.0 * PI * p).sin(),
+            Waveform::Noise => self.noise,
+        };
+        self.phase += self.tone.pitch / self.rate;
+        if self.phase >= 1.0 {
+            self.phase %= 1.0;
+            self.noise = self.rng.next_u8() as f32 / 127.5 - 1.0;
+        }
+        wave
+    }
+
+    /// Move the envelope a sample's worth towards full or nothing
+    fn step_envelope(&mut self) {
+        let (target, time) = if self.on {
+            (1.0, self.tone.attack)
+        } else {
+            (0.0, self.tone.release)
+        };
+        let step = 1.0 / (time * self.rate).max(1.0);
+        self.level = if self.level < target {
+            (self.level + step).min(target)
+        } else {
+            (self.level - step).max(target)
+        };
+    }
+}
+
+/// Records the beeper to a 16-bit mono PCM WAV file, passing every call on
+/// to another beeper as well; `()` records alone. Samples are synthesized
+/// up to each time `advance_to` gives, so the recording follows emulated
+/// time however fast the host runs.
+///
+/// The file's sizes are filled in by `finish`, or when it is dropped.
+/// Write errors are kept until then, since beepers cannot fail
+pub struct WavBeeper<W: Write + Seek, B: Beeper = ()> {
+    inner: B,
+    out: W,
+    synth: Synth,
+    rate: u32,
+
+    /// Samples written so far
+    samples: u64,
+    error: Option<io::Error>,
+    finished: bool,
+}
+
+impl<W: Write + Seek, B: Beeper> WavBeeper<W, B> {
+    /// Start a recording of `tone` at `rate` samples per second
+    pub fn new(mut out: W, tone: Tone, rate: u32, inner: B) -> io::Result<Self> {
+        out.write_all(&wav_header(rate, 0))?;
+        Ok(WavBeeper {
+            inner,
+            out,
+            synth: Synth::new(tone, rate),
+            rate,
+            samples: 0,
+            error: None,
+            finished: false,
+        })
+    }
+
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    /// Samples written so far
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Fill in the file's sizes, reporting any error since it was created.
+    /// Later calls change nothing
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            self.finished = true;
+            return Err(e);
+        }
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let bytes = self.samples * 2;
+        if bytes > u64::from(u32::MAX) - 36 {
+            return Err(io::Error::other("WAV file too long"));
+        }
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&wav_header(self.rate, bytes as u32))?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write + Seek, B: Beeper> Beeper for WavBeeper<W, B> {
+    fn beep_on(&mut self) {
+        self.synth.set_on(true);
+        self.inner.beep_on();
+    }
+
+    fn beep_off(&mut self) {
+        self.synth.set_on(false);
+        self.inner.beep_off();
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], rate: f32) {
+        self.synth.set_pattern(pattern, rate);
+        self.inner.set_pattern(pattern, rate);
+    }
+
+    fn advance_to(&mut self, seconds: f64) {
+        let due = (seconds * self.rate as f64) as u64;
+        if self.error.is_none() && !self.finished && due > self.samples {
+            let mut data = Vec::with_capacity((due - self.samples) as usize * 2);
+            for _ in self.samples..due {
+                let sample = self.synth.next_sample().clamp(-1.0, 1.0);
+                let sample = (sample * i16::MAX as f32) as i16;
+                data.extend_from_slice(&sample.to_le_bytes());
+            }
+            match self.out.write_all(&data) {
+                Ok(()) => self.samples = due,
+                Err(e) => self.error = Some(e),
+            }
+        }
+        self.inner.advance_to(seconds);
+    }
+}
+
+impl<W: Write + Seek, B: Beeper> Drop for WavBeeper<W, B> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// A RIFF header for `bytes` bytes of 16-bit mono samples
+fn wav_header(rate: u32, bytes: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(36 + bytes).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // mono
+    header.extend_from_slice(&rate.to_le_bytes());
+    header.extend_from_slice(&(rate * 2).to_le_bytes()); // bytes per second
+    header.extend_from_slice(&2u16.to_le_bytes()); // bytes per sample
+    header.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&bytes.to_le_bytes());
+    header
+}
@@ -0,0 +1,307 @@
+use std::error::Error;
+use std::fmt;
+
+use super::{Beeper, Chip8, Display, Input, Quirks, Xorshift};
+
+/// Every save state starts with these bytes
+const MAGIC: &[u8; 4] = b"C8ST";
+
+/// Bumped whenever the layout below changes
+pub const STATE_VERSION: u8 = 1;
+
+/// Why a save state could not be restored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state magic
+    BadMagic,
+
+    /// The state was written by a different version of the format
+    UnsupportedVersion(u8),
+
+    /// The state was saved while a different ROM was loaded
+    RomMismatch { expected: u64, found: u64 },
+
+    /// The data ended early
+    Truncated,
+
+    /// A field holds a value no machine could be in
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "Save state version {} is not supported, expected {}",
+                    v,
+                    STATE_VERSION
+                )
+            }
+            StateError::RomMismatch { expected, found } => {
+                write!(
+                    f,
+                    "Save state is for ROM {:016x}, but ROM {:016x} is loaded",
+                    found,
+                    expected
+                )
+            }
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::Corrupt(what) => write!(f, "Save state has a corrupt {}", what),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// 64-bit FNV-1a of a ROM image, stored in save states to tell ROMs apart
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in rom {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// The quirks, one bit each, in declaration order
+fn quirk_bits(q: &Quirks) -> u8 {
+    (q.shift as u8) | (q.load_store as u8) << 1 | (q.jump as u8) << 2 | (q.vf_reset as u8) << 3 |
+        (q.clip as u8) << 4 | (q.display_wait as u8) << 5 | (q.extended_memory as u8) << 6 |
+        (q.index_overflow as u8) << 7
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    Quirks {
+        shift: bits & 1 != 0,
+        load_store: bits & 1 << 1 != 0,
+        jump: bits & 1 << 2 != 0,
+        vf_reset: bits & 1 << 3 != 0,
+        clip: bits & 1 << 4 != 0,
+        display_wait: bits & 1 << 5 != 0,
+        extended_memory: bits & 1 << 6 != 0,
+        index_overflow: bits & 1 << 7 != 0,
+    }
+}
+
+impl<D: Display, I: Input, B: Beeper> Chip8<D, I, B> {
+    /// Hash of the ROM passed to `load`
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Snapshot the whole machine. The result starts with a header holding
+    /// the magic `C8ST`, the format version and the ROM hash; the rest is
+    /// zero run-length encoded, which keeps mostly empty memory small
+    pub fn save_state(&self) -> Vec<u8> {
+        let body = self.snapshot();
+        let mut out = Vec::with_capacity(body.len() / 4);
+        out.extend_from_slice(MAGIC);
+        out.push(STATE_VERSION);
+        push_u64(&mut out, self.rom_hash);
+        compress(&body, &mut out);
+        out
+    }
+
+    /// Restore a snapshot taken by `save_state`. The same ROM must already
+    /// be loaded; on error the machine is left untouched
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let mut header = Reader::new(&data[MAGIC.len()..]);
+        let version = header.u8()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let hash = header.u64()?;
+        if hash != self.rom_hash {
+            return Err(StateError::RomMismatch {
+                expected: self.rom_hash,
+                found: hash,
+            });
+        }
+        self.restore(&decompress(header.rest())?)
+    }
+
+    /// The uncompressed body of a save state
+    pub(crate) fn snapshot(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(self.memory.len() + 256);
+        body.push(quirk_bits(&self.quirks));
+        body.extend_from_slice(&self.register);
+        push_u16(&mut body, self.pc);
+        push_u16(&mut body, self.address_reg);
+        body.push(self.delay_timer);
+        body.push(self.sound_timer);
+        body.push(self.stack.len() as u8);
+        for frame in &self.stack {
+            push_u16(&mut body, *frame);
+        }
+        match self.key {
+            Some(k) => body.extend_from_slice(&[1, k]),
+            None => body.extend_from_slice(&[0, 0]),
+        }
+        push_u64(&mut body, self.rng.state());
+        body.extend_from_slice(&self.rpl);
+        body.push(self.plane);
+        body.extend_from_slice(&self.pattern);
+        body.push(self.pitch);
+        body.push(self.halt as u8 | (self.vblank_wait as u8) << 1 | (self.grid.hires() as u8) << 2);
+        body.extend_from_slice(self.grid.pixels());
+        body.extend_from_slice(&self.memory);
+        body
+    }
+
+    /// Restore the body written by `snapshot`, leaving the machine untouched
+    /// on error
+    pub(crate) fn restore(&mut self, body: &[u8]) -> Result<(), StateError> {
+        let mut r = Reader::new(body);
+        let quirks = quirks_from_bits(r.u8()?);
+        let mut register = [0; 16];
+        register.copy_from_slice(r.bytes(16)?);
+        let pc = r.u16()?;
+        let address_reg = r.u16()?;
+        let delay_timer = r.u8()?;
+        let sound_timer = r.u8()?;
+        let depth = r.u8()? as usize;
+        if depth > 24 {
+            return Err(StateError::Corrupt("call stack"));
+        }
+        let mut stack = Vec::with_capacity(24);
+        for _ in 0..depth {
+            stack.push(r.u16()?);
+        }
+        let key = match (r.u8()?, r.u8()?) {
+            (0, _) => None,
+            (_, k) => Some(k),
+        };
+        let rng = Xorshift::from_state(r.u64()?);
+        let mut rpl = [0; 16];
+        rpl.copy_from_slice(r.bytes(16)?);
+        let plane = r.u8()?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(r.bytes(16)?);
+        let pitch = r.u8()?;
+        let flags = r.u8()?;
+        let hires = flags & 1 << 2 != 0;
+        let mut grid = self.grid.clone();
+        grid.set_hires(hires);
+        let pixels = r.bytes(grid.width() * grid.height())?;
+        let size = if quirks.extended_memory { 0x10000 } else { 0x1000 };
+        let memory = r.bytes(size)?.to_vec();
+        if !r.rest().is_empty() {
+            return Err(StateError::Corrupt("length"));
+        }
+        grid.restore(pixels);
+        let audio_changed = pattern != self.pattern || pitch != self.pitch;
+
+        self.quirks = quirks;
+        self.register = register;
+        self.pc = pc;
+        self.address_reg = address_reg;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.stack = stack;
+        self.key = key;
+        self.rng = rng;
+        self.rpl = rpl;
+        self.plane = plane;
+        self.pattern = pattern;
+        self.pitch = pitch;
+        self.halt = flags & 1 != 0;
+        self.vblank_wait = flags & 1 << 1 != 0;
+        self.grid = grid;
+        self.memory = memory;
+        if audio_changed {
+            self.beep.set_pattern(&self.pattern, super::pattern_rate(pitch));
+        }
+        self.update_beeper();
+        // A display that fails here fails again on the program's next draw,
+        // which reports it
+        let _ = self.redraw();
+        Ok(())
+    }
+}
+
+fn push_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&[(v >> 8) as u8, v as u8]);
+}
+
+fn push_u64(out: &mut Vec<u8>, v: u64) {
+    for i in (0..8).rev() {
+        out.push((v >> (i * 8)) as u8);
+    }
+}
+
+/// Zeros are written as a 0 followed by the length of the run, 1-255; every
+/// other byte is written as is
+pub(crate) fn compress(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] == 0 {
+            let run = data[i..].iter().take(255).take_while(|b| **b == 0).count();
+            out.push(0);
+            out.push(run as u8);
+            i += run;
+        } else {
+            out.push(data[i]);
+            i += 1;
+        }
+    }
+}
+
+pub(crate) fn decompress(data: &[u8]) -> Result<Vec<u8>, StateError> {
+    let mut out = Vec::with_capacity(data.len() * 4);
+    let mut r = Reader::new(data);
+    while !r.rest().is_empty() {
+        match r.u8()? {
+            0 => {
+                let run = r.u8()? as usize;
+                if run == 0 {
+                    return Err(StateError::Corrupt("zero run"));
+                }
+                out.resize(out.len() + run, 0);
+            }
+            b => out.push(b),
+        }
+    }
+    Ok(out)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < n {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let b = self.bytes(2)?;
+        Ok((b[0] as u16) << 8 | b[1] as u16)
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(self.bytes(8)?.iter().fold(0, |v, b| v << 8 | *b as u64))
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+}
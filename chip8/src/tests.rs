@@ -4,7 +4,7 @@ struct NoopDisplay {}
 impl Display for NoopDisplay {
     fn clear(&mut self) {}
 
-    fn draw(&mut self, _: &[bool; 32 * 64]) -> Result<(), String> {
+    fn draw(&mut self, _: &Framebuffer) -> Result<(), String> {
         Ok(())
     }
 }
@@ -15,62 +15,130 @@ impl Beeper for NoopBeeper {
     fn beep_off(&mut self) {}
 }
 
+struct MockBeeper {
+    on: bool,
+    toggles: usize,
+    pattern: Option<([u8; 16], f32)>,
+}
+
+impl Beeper for MockBeeper {
+    fn beep_on(&mut self) {
+        self.on = true;
+        self.toggles += 1;
+    }
+    fn beep_off(&mut self) {
+        self.on = false;
+        self.toggles += 1;
+    }
+    fn set_pattern(&mut self, pattern: &[u8; 16], rate: f32) {
+        self.pattern = Some((*pattern, rate));
+    }
+}
+
 struct MockInput {
-    keys: [bool; 16],
-    block_key: u8,
+    keys: u16,
 }
 
 impl MockInput {
     fn new() -> Self {
         MockInput {
-            keys: [false; 16],
-            block_key: 0x10,
+            keys: 0,
         }
     }
 }
 
 impl Input for MockInput {
-    fn block_for(&mut self) -> Option<u8> {
-        return Some(self.block_key);
+    fn keys(&self) -> u16 {
+        self.keys
     }
 
-    fn key(&mut self, key: u8) -> bool {
-        return self.keys[key as usize];
+    fn set_key(&mut self, key: u8, pressed: bool) {
+        if pressed {
+            self.keys |= 1 << key;
+        } else {
+            self.keys &= !(1 << key);
+        }
     }
 }
 
+fn chip8(quirks: Quirks) -> Chip8<NoopDisplay, MockInput, NoopBeeper> {
+    Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {}, quirks)
+}
+
 #[test]
 fn opcode_bits() {
     let code = 0xCABC;
-    assert_eq!(0xC, super::opcode_n1(code));
-    assert_eq!(0xABC, super::opcode_addr(code));
-    assert_eq!(0xA, super::opcode_regx(code));
-    assert_eq!(0xB, super::opcode_regy(code));
-    assert_eq!(0b1010, super::opcode_nibble4(0b1111111110001010));
-    assert_eq!(0xBC, super::opcode_byte2(code));
+    assert_eq!(0xC, disasm::opcode_n1(code));
+    assert_eq!(0xABC, disasm::opcode_addr(code));
+    assert_eq!(0xA, disasm::opcode_regx(code));
+    assert_eq!(0xB, disasm::opcode_regy(code));
+    assert_eq!(0b1010, disasm::opcode_nibble4(0b1111111110001010));
+    assert_eq!(0xBC, disasm::opcode_byte2(code));
 }
 
 #[test]
-#[should_panic(expected = "Invalid instruction '0xFF 0xFF' at PC '0x200'")]
 fn bad_rom() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
-    c.load(vec![0xFF, 0xFF]);
-    c.cycle();
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0xFF, 0xFF]).unwrap();
+    let e = c.cycle().unwrap_err();
+    assert_eq!(
+        Chip8Error::InvalidOpcode {
+            opcode: 0xFFFF,
+            address: 0x200,
+        },
+        e
+    );
+    assert_eq!("Invalid instruction '0xFF 0xFF' at PC '0x200'", e.to_string());
+}
+
+#[test]
+fn bad_opcode_at_top_of_memory() {
+    // Fetching from 0xFFFE wraps PC round to 0, but errors still point at
+    // the instruction
+    let mut c = chip8(Quirks::xo_chip());
+    c.memory[0xFFFE] = 0xFF;
+    c.memory[0xFFFF] = 0xFF;
+    c.pc = 0xFFFE;
+    assert_eq!(
+        Err(Chip8Error::InvalidOpcode {
+            opcode: 0xFFFF,
+            address: 0xFFFE,
+        }),
+        c.cycle()
+    );
+
+    c.memory[0xFFFE] = 0x00;
+    c.memory[0xFFFF] = 0xEE;
+    c.pc = 0xFFFE;
+    assert_eq!(Err(Chip8Error::StackUnderflow { address: 0xFFFE }), c.cycle());
+}
+
+#[test]
+fn rom_too_large() {
+    let mut c = chip8(Quirks::default());
+    assert_eq!(
+        Err(Chip8Error::RomTooLarge {
+            size: 4096,
+            max: 4096 - 512,
+        }),
+        c.load(vec![0; 4096])
+    );
+    c.load(vec![0; 4096 - 512]).unwrap();
 }
 
 #[test]
 fn goto() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
-    c.load(vec![0x1A, 0xBC]);
-    c.cycle();
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0x1A, 0xBC]).unwrap();
+    c.cycle().unwrap();
     assert_eq!(c.pc, 0xABC);
 }
 
 #[test]
 fn call() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
-    c.load(vec![0x2A, 0xBC]);
-    c.cycle();
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0x2A, 0xBC]).unwrap();
+    c.cycle().unwrap();
     assert_eq!(c.pc, 0xABC);
     assert_eq!(1, c.stack.len());
     assert_eq!(0x202, c.stack[0]);
@@ -78,410 +146,657 @@ fn call() {
 
 #[test]
 fn ret() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
-    c.load(vec![0x22, 0x04, 0xFF, 0xFF, 0x00, 0xEE]);
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0x22, 0x04, 0xFF, 0xFF, 0x00, 0xEE]).unwrap();
     // CALL 0x202
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(c.pc, 0x204);
     assert_eq!(1, c.stack.len());
     assert_eq!(0x202, c.stack[0]);
 
     // RET
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0, c.stack.len());
     assert_eq!(c.pc, 0x202);
 }
 
+#[test]
+fn ret_empty_stack() {
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0x00, 0xEE]).unwrap();
+    assert_eq!(Err(Chip8Error::StackUnderflow { address: 0x200 }), c.cycle());
+}
+
+#[test]
+fn call_full_stack() {
+    let mut c = chip8(Quirks::default());
+    // CALL 0x200, forever
+    c.load(vec![0x22, 0x00]).unwrap();
+    for _ in 0..24 {
+        c.cycle().unwrap();
+    }
+    assert_eq!(Err(Chip8Error::StackOverflow { address: 0x200 }), c.cycle());
+    assert_eq!(24, c.stack.len());
+}
+
 #[test]
 fn skip_eq() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0xF0;
-    c.load(vec![0x3A, 0xF0]);
+    c.load(vec![0x3A, 0xF0]).unwrap();
 
     // Skip EQ
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x204, c.pc);
 
     // try, expect no skip
     c.pc = 0x200;
     c.register[0xA] = 0x0;
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x202, c.pc);
 
 }
 
 #[test]
 fn skip_neq() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0xF0;
-    c.load(vec![0x4A, 0xF0]);
+    c.load(vec![0x4A, 0xF0]).unwrap();
 
     // Skip EQ
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x202, c.pc);
 
     // try, expect no skip
     c.pc = 0x202;
     c.register[0xA] = 0x0;
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x204, c.pc);
 }
 
 #[test]
 fn skip_neqr() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0xF0;
     c.register[0xB] = 0xF1;
-    c.load(vec![0x5A, 0xB0, 0x5A, 0xB0, 0xFF, 0xFF]);
+    c.load(vec![0x5A, 0xB0, 0x5A, 0xB0, 0xFF, 0xFF]).unwrap();
 
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x202, c.pc);
 
     c.register[0xB] = 0xF0;
     // Skip EQ
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x206, c.pc);
 }
 
 #[test]
-#[should_panic(expected = "Invalid instruction '0x5A 0xB1' at PC '0x200'")]
-fn skip_neqr_panic() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+fn skip_neqr_ignores_low_nibble() {
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0xF0;
     c.register[0xB] = 0xF1;
-    c.load(vec![0x5A, 0xB1]);
+    c.load(vec![0x9A, 0xB7]).unwrap();
+    c.cycle().unwrap();
+    assert_eq!(0x204, c.pc);
+}
 
-    c.cycle();
+#[test]
+fn skip_neqr_invalid() {
+    let mut c = chip8(Quirks::default());
+    c.register[0xA] = 0xF0;
+    c.register[0xB] = 0xF1;
+    c.load(vec![0x5A, 0xB1]).unwrap();
+
+    assert_eq!(
+        Err(Chip8Error::InvalidOpcode {
+            opcode: 0x5AB1,
+            address: 0x200,
+        }),
+        c.cycle()
+    );
 }
 
 #[test]
 fn set_r() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
-    c.load(vec![0x6F, 0xF0]);
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0x6F, 0xF0]).unwrap();
 
     // SET VF to 0xF0
     assert_eq!(0x00, c.register[VF]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x202, c.pc);
     assert_eq!(0xF0, c.register[VF]);
 }
 
 #[test]
 fn add() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0x10;
-    c.load(vec![0x7A, 0xFF]);
+    c.load(vec![0x7A, 0xFF]).unwrap();
 
     assert_eq!(0x00, c.register[VF]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x00, c.register[VF]);
     assert_eq!(0xF, c.register[0xA]);
 }
 
 #[test]
 fn copyr() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0x10;
-    c.load(vec![0x8A, 0xB0]);
+    c.load(vec![0x8A, 0xB0]).unwrap();
 
     // SET VF to 0xF0
     assert_eq!(0x10, c.register[0xA]);
     assert_eq!(0x00, c.register[0xB]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x00, c.register[0xA]);
     assert_eq!(0x00, c.register[0xB]);
 }
 
 #[test]
 fn or() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0x10;
     c.register[0xB] = 0x01;
-    c.load(vec![0x8A, 0xB1]);
+    c.load(vec![0x8A, 0xB1]).unwrap();
 
     // SET VF to 0xF0
     assert_eq!(0x10, c.register[0xA]);
     assert_eq!(0x01, c.register[0xB]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x11, c.register[0xA]);
     assert_eq!(0x01, c.register[0xB]);
 }
 
 #[test]
 fn and() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0x10;
     c.register[0xB] = 0x01;
-    c.load(vec![0x8A, 0xB2]);
+    c.load(vec![0x8A, 0xB2]).unwrap();
 
     // SET VF to 0xF0
     assert_eq!(0x10, c.register[0xA]);
     assert_eq!(0x01, c.register[0xB]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x00, c.register[0xA]);
     assert_eq!(0x01, c.register[0xB]);
 }
 
 #[test]
 fn xor() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0x10;
     c.register[0xB] = 0x01;
-    c.load(vec![0x8A, 0xB3]);
+    c.load(vec![0x8A, 0xB3]).unwrap();
 
     assert_eq!(0x10, c.register[0xA]);
     assert_eq!(0x01, c.register[0xB]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x11, c.register[0xA]);
     assert_eq!(0x01, c.register[0xB]);
 }
 
+#[test]
+fn vf_reset() {
+    for &(op, quirks, vf) in &[
+        (0xB1, Quirks::cosmac_vip(), 0),
+        (0xB2, Quirks::cosmac_vip(), 0),
+        (0xB3, Quirks::cosmac_vip(), 0),
+        (0xB1, Quirks::chip48(), 1),
+        (0xB2, Quirks::chip48(), 1),
+        (0xB3, Quirks::chip48(), 1),
+    ]
+    {
+        let mut c = chip8(quirks);
+        c.register[VF] = 1;
+        c.load(vec![0x8A, op]).unwrap();
+        c.cycle().unwrap();
+        assert_eq!(vf, c.register[VF]);
+    }
+}
+
 #[test]
 fn addeq() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0xFF;
     c.register[0xb] = 0x10;
-    c.load(vec![0x8A, 0xB4]);
+    c.load(vec![0x8A, 0xB4]).unwrap();
 
     assert_eq!(0x00, c.register[VF]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x01, c.register[VF]);
     assert_eq!(0xF, c.register[0xA]);
 }
 
 #[test]
 fn subeq() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0x10;
     c.register[0xB] = 0xFF;
-    c.load(vec![0x8b, 0xa5]);
+    c.load(vec![0x8b, 0xa5]).unwrap();
 
     assert_eq!(0x00, c.register[VF]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x01, c.register[VF]);
     assert_eq!(0x10, c.register[0xA]);
 }
 
 #[test]
 fn rshift() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0x10;
     c.register[0xB] = 0b11;
-    c.load(vec![0x8A, 0xB6, 0x8A, 0xB6, 0x8A, 0xB6]);
+    c.load(vec![0x8A, 0xB6, 0x8A, 0xB6]).unwrap();
 
-    // SET VF to 0xF0
+    // VA = VB >> 1, VB is left alone
+    assert_eq!(0x00, c.register[VF]);
+    c.cycle().unwrap();
+    assert_eq!(0b01, c.register[0xA]);
+    assert_eq!(0b11, c.register[0xB]);
+    assert_eq!(0x01, c.register[VF]);
+
+    c.register[0xB] = 0b10;
+    c.cycle().unwrap();
+    assert_eq!(0b01, c.register[0xA]);
+    assert_eq!(0b10, c.register[0xB]);
     assert_eq!(0x00, c.register[VF]);
-    c.cycle();
+}
+
+#[test]
+fn rshift_quirk() {
+    let mut c = chip8(Quirks::chip48());
+    c.register[0xA] = 0b11;
+    c.register[0xB] = 0xFF;
+    c.load(vec![0x8A, 0xB6, 0x8A, 0xB6, 0x8A, 0xB6]).unwrap();
+
+    // VA is shifted in place, VB is ignored
+    c.cycle().unwrap();
     assert_eq!(0b01, c.register[0xA]);
-    assert_eq!(0b01, c.register[0xB]);
+    assert_eq!(0xFF, c.register[0xB]);
     assert_eq!(0x01, c.register[VF]);
 
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0b00, c.register[0xA]);
-    assert_eq!(0b00, c.register[0xB]);
     assert_eq!(0x01, c.register[VF]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0b00, c.register[0xA]);
-    assert_eq!(0b00, c.register[0xB]);
     assert_eq!(0x00, c.register[VF]);
 }
 
 #[test]
 fn subeq2() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0x10;
     c.register[0xB] = 0xFF;
-    c.load(vec![0x8b, 0xa7]);
+    c.load(vec![0x8b, 0xa7]).unwrap();
 
     assert_eq!(0x00, c.register[VF]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x01, c.register[VF]);
     assert_eq!(0x10, c.register[0xA]);
 }
 
 #[test]
 fn lshift() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0x10;
     c.register[0xB] = 0b11000000;
-    c.load(vec![0x8A, 0xBE, 0x8A, 0xBE, 0x8A, 0xBE]);
+    c.load(vec![0x8A, 0xBE, 0x8A, 0xBE]).unwrap();
 
-    // SET VF to 0xF0
+    // VA = VB << 1, VB is left alone
     assert_eq!(0x00, c.register[VF]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0b10000000, c.register[0xA]);
-    assert_eq!(0b10000000, c.register[0xB]);
+    assert_eq!(0b11000000, c.register[0xB]);
     assert_eq!(0x01, c.register[VF]);
 
-    c.cycle();
+    c.register[0xB] = 0b01000000;
+    c.cycle().unwrap();
+    assert_eq!(0b10000000, c.register[0xA]);
+    assert_eq!(0b01000000, c.register[0xB]);
+    assert_eq!(0x00, c.register[VF]);
+}
+
+#[test]
+fn lshift_quirk() {
+    let mut c = chip8(Quirks::chip48());
+    c.register[0xA] = 0b11000000;
+    c.register[0xB] = 0xFF;
+    c.load(vec![0x8A, 0xBE, 0x8A, 0xBE, 0x8A, 0xBE]).unwrap();
+
+    // VA is shifted in place, VB is ignored
+    c.cycle().unwrap();
+    assert_eq!(0b10000000, c.register[0xA]);
+    assert_eq!(0xFF, c.register[0xB]);
+    assert_eq!(0x01, c.register[VF]);
+
+    c.cycle().unwrap();
     assert_eq!(0b00000000, c.register[0xA]);
-    assert_eq!(0b00000000, c.register[0xB]);
     assert_eq!(0x01, c.register[VF]);
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0b00000000, c.register[0xA]);
-    assert_eq!(0b00000000, c.register[0xB]);
     assert_eq!(0x00, c.register[VF]);
 }
 
 #[test]
 fn skip_neqr2() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 0xFA;
     c.register[0xB] = 0xFA;
-    c.load(vec![0x9A, 0xB0]);
+    c.load(vec![0x9A, 0xB0]).unwrap();
 
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x202, c.pc);
 
     c.pc = 0x200;
     c.register[0xB] = 0xFB;
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x204, c.pc);
 }
 
 #[test]
 fn mem() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     assert_eq!(0x0, c.address_reg);
-    c.load(vec![0xAA, 0xBC]);
+    c.load(vec![0xAA, 0xBC]).unwrap();
 
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0xABC, c.address_reg);
     assert_eq!(0x202, c.pc);
 }
 
 #[test]
 fn jmp() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[V0] = 0xA0;
-    c.load(vec![0xb2, 0x01]);
+    c.load(vec![0xb2, 0x01]).unwrap();
 
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x2A1, c.pc);
 }
 
+#[test]
+fn jmp_quirk() {
+    let mut c = chip8(Quirks::chip48());
+    c.register[V0] = 0xA0;
+    c.register[V2] = 0x10;
+    c.load(vec![0xb2, 0x01]).unwrap();
+
+    // BXNN: 0x201 + V2
+    c.cycle().unwrap();
+    assert_eq!(0x211, c.pc);
+}
+
 #[test]
 fn rand() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
-    let mask = c.rng.gen::<u8>();
-    c.load(vec![0xCA, mask]);
+    let mut c = chip8(Quirks::default());
+    let mask = c.rng.next_u8();
+    c.load(vec![0xCA, mask]).unwrap();
 
     for _ in 0..1000 {
-        c.cycle();
+        c.cycle().unwrap();
         assert!(c.register[0xA] <= mask);
         c.pc = 0x200;
     }
 }
 
+#[test]
+fn rand_seeded() {
+    let run = |seed| {
+        let mut c = chip8(Quirks::default());
+        c.seed_rng(seed);
+        c.load(vec![0xCA, 0xFF]).unwrap();
+        (0..32)
+            .map(|_| {
+                c.pc = 0x200;
+                c.cycle().unwrap();
+                c.register[VA]
+            })
+            .collect::<Vec<u8>>()
+    };
+
+    assert_eq!(run(42), run(42));
+    assert_ne!(run(42), run(43));
+}
+
+#[test]
+fn xorshift() {
+    let mut a = Xorshift::new(0);
+    let first: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+    assert!(first.iter().all(|n| *n != 0));
+
+    // Restoring the state picks up where the generator left off
+    let mut b = Xorshift::from_state(a.state());
+    assert_eq!(a.next_u64(), b.next_u64());
+    assert_eq!(Xorshift::new(0).next_u64(), first[0]);
+}
+
 #[test]
 fn draw() {
     // TODO: test grid
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     for x in 0..0x10 {
         c.pc = 0x200;
         c.register[VF] = 0x0;
-        c.load(vec![0xDA, 0xB0 + x]);
-        c.cycle();
+        c.load(vec![0xDA, 0xB0 + x]).unwrap();
+        c.cycle().unwrap();
     }
 }
 
 #[test]
 fn key_press() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
-    c.input.keys[VA] = true;
+    let mut c = chip8(Quirks::default());
+    c.set_key(0xA, true);
     c.register[VA] = 0xA;
-    c.load(vec![0xEA, 0x9E]);
+    c.load(vec![0xEA, 0x9E]).unwrap();
 
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x204, c.pc);
 
 
-    c.input.keys[VA] = false;
+    c.set_key(0xA, false);
+    c.pc = 0x200;
+    c.cycle().unwrap();
+    assert_eq!(0x202, c.pc);
+
+    // Only the low nibble names a key, and 0x1A is none of them
+    c.set_key(0xA, true);
+    c.register[VA] = 0x1A;
     c.pc = 0x200;
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x202, c.pc);
 }
 
 #[test]
 fn key_nopress() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
-    c.input.keys[VA] = true;
+    let mut c = chip8(Quirks::default());
+    c.set_key(0xA, true);
     c.register[VA] = 0xA;
-    c.load(vec![0xEA, 0xA1]);
+    c.load(vec![0xEA, 0xA1]).unwrap();
 
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x202, c.pc);
 
 
-    c.input.keys[VA] = false;
+    c.set_key(0xA, false);
     c.pc = 0x200;
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(0x204, c.pc);
 }
 
 #[test]
 fn get_delay() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.delay_timer = 240;
-    c.load(vec![0xFA, 0x07]);
+    c.load(vec![0xFA, 0x07]).unwrap();
 
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(240, c.register[0xA]);
 }
 
 #[test]
 fn get_key() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
-    c.input.block_key = 0xA;
-    c.load(vec![0xFA, 0x0A]);
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0xFA, 0x0A]).unwrap();
+
+    // Nothing happens until a key goes down and comes back up
+    c.cycle().unwrap();
+    assert!(c.waiting_for_key());
+    c.set_key(0xB, true);
+    c.cycle().unwrap();
+    c.set_key(0x3, true);
+    c.cycle().unwrap();
+    assert_eq!(0x200, c.pc);
+
+    // Other keys coming and going make no difference
+    c.set_key(0x3, false);
+    c.cycle().unwrap();
+    assert_eq!(0x200, c.pc);
+    c.set_key(0xB, false);
+    c.cycle().unwrap();
+    assert_eq!(0x202, c.pc);
+    assert_eq!(0xB, c.register[VA]);
+    assert!(!c.waiting_for_key());
+}
 
-    c.cycle();
-    assert_eq!(0xA, c.register[VA])
+#[test]
+fn set_key_past_f() {
+    let mut c = chip8(Quirks::default());
+    c.set_key(0x10, true);
+    c.set_key(0xFF, true);
+    assert_eq!(0, c.input_mut().keys());
+    c.set_key(0xF, true);
+    assert_eq!(0x8000, c.input_mut().keys());
 }
 
 #[test]
 fn set_delay() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 42;
-    c.load(vec![0xFA, 0x15]);
+    c.load(vec![0xFA, 0x15]).unwrap();
 
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(42, c.delay_timer);
 }
 
 #[test]
 fn set_sound() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 42;
-    c.load(vec![0xFA, 0x18]);
+    c.load(vec![0xFA, 0x18]).unwrap();
 
-    c.cycle();
+    c.cycle().unwrap();
     assert_eq!(42, c.sound_timer);
 }
 
+#[test]
+fn tick_timers() {
+    let mut c = chip8(Quirks::default());
+    c.delay_timer = 2;
+    c.sound_timer = 1;
+
+    c.tick_timers();
+    assert_eq!(1, c.delay_timer);
+    assert_eq!(0, c.sound_timer);
+
+    c.tick_timers();
+    c.tick_timers();
+    assert_eq!(0, c.delay_timer);
+    assert_eq!(0, c.sound_timer);
+}
+
+#[test]
+fn timers_ignore_cycles() {
+    let mut c = chip8(Quirks::default());
+    c.delay_timer = 60;
+    // JP 0x200
+    c.load(vec![0x12, 0x00]).unwrap();
+    for _ in 0..1000 {
+        c.cycle().unwrap();
+    }
+    assert_eq!(60, c.delay_timer);
+}
+
+#[test]
+fn beeper_follows_sound_timer() {
+    let beeper = MockBeeper {
+        on: false,
+        toggles: 0,
+        pattern: None,
+    };
+    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), beeper, Quirks::default());
+    c.register[VA] = 3;
+    c.load(vec![0xFA, 0x18]).unwrap();
+
+    c.cycle().unwrap();
+    assert!(c.beep.on);
+
+    // Stays on, without being toggled, until the timer runs out
+    c.tick_timers();
+    c.tick_timers();
+    assert!(c.beep.on);
+    assert_eq!(1, c.beep.toggles);
+    c.tick_timers();
+    assert!(!c.beep.on);
+    assert_eq!(2, c.beep.toggles);
+
+    // Setting the timer to 0 silences it right away
+    c.pc = 0x200;
+    c.cycle().unwrap();
+    assert!(c.beep.on);
+    c.register[VA] = 0;
+    c.pc = 0x200;
+    c.cycle().unwrap();
+    assert!(!c.beep.on);
+}
+
 #[test]
 fn addi() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[0xA] = 42;
     c.address_reg = 4095;
-    c.load(vec![0xFA, 0x1E]);
+    c.load(vec![0xFA, 0x1E]).unwrap();
 
     c.register[VF] = 1;
+    c.cycle().unwrap();
+    assert_eq!(4137, c.address_reg);
+    // The VIP leaves VF alone
     assert_eq!(1, c.register[VF]);
-    c.cycle();
-    assert_eq!(0, c.register[VF]);
+}
+
+#[test]
+fn addi_index_overflow() {
+    let mut c = chip8(Quirks {
+        index_overflow: true,
+        ..Quirks::default()
+    });
+    c.register[0xA] = 42;
+    c.load(vec![0xFA, 0x1E]).unwrap();
+
+    // Past 0xFFF sets VF, and staying within clears it
+    c.address_reg = 4095;
+    c.cycle().unwrap();
     assert_eq!(4137, c.address_reg);
+    assert_eq!(1, c.register[VF]);
+
+    c.pc = 0x200;
+    c.address_reg = 0x100;
+    c.cycle().unwrap();
+    assert_eq!(0x12A, c.address_reg);
+    assert_eq!(0, c.register[VF]);
 }
 
 #[test]
 fn sprite() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     for x in 0x0..0x10 {
         c.pc = 0x200;
         c.register[VA] = x;
         c.address_reg = 2000;
-        c.load(vec![0xFA, 0x29]);
-        c.cycle();
+        c.load(vec![0xFA, 0x29]).unwrap();
+        c.cycle().unwrap();
         assert_eq!(x as u16 * 5, c.address_reg);
 
         for n in 0..5 {
@@ -490,13 +805,235 @@ fn sprite() {
     }
 }
 
+#[test]
+fn draw_failure() {
+    struct BrokenDisplay {}
+    impl Display for BrokenDisplay {
+        fn clear(&mut self) {}
+
+        fn draw(&mut self, _: &Framebuffer) -> Result<(), String> {
+            Err("gone".to_string())
+        }
+    }
+
+    let mut c = Chip8::new(BrokenDisplay {}, MockInput::new(), NoopBeeper {}, Quirks::default());
+    c.load(vec![0xDA, 0xB1]).unwrap();
+    assert_eq!(Err(Chip8Error::Display("gone".to_string())), c.cycle());
+}
+
+#[test]
+fn draw_wraps() {
+    let mut c = chip8(Quirks {
+        clip: false,
+        ..Quirks::default()
+    });
+    c.register[VA] = 62;
+    c.register[VB] = 31;
+    // Font sprite for 0, 0xF0 on the first row
+    c.address_reg = 0;
+    c.load(vec![0xDA, 0xB2]).unwrap();
+    c.cycle().unwrap();
+
+    assert_ne!(0, c.grid.pixels()[31 * 64 + 62]);
+    assert_ne!(0, c.grid.pixels()[31 * 64 + 63]);
+    assert_ne!(0, c.grid.pixels()[31 * 64]);
+    assert_ne!(0, c.grid.pixels()[31 * 64 + 1]);
+    // Second row, 0x90, wraps to the top
+    assert_ne!(0, c.grid.pixels()[62]);
+    assert_eq!(0, c.grid.pixels()[0]);
+    assert_ne!(0, c.grid.pixels()[1]);
+}
+
+#[test]
+fn dirty_rects() {
+    let rect = |x, y, width, height| Rect { x, y, width, height };
+    let quirks = Quirks {
+        clip: false,
+        display_wait: false,
+        ..Quirks::default()
+    };
+    // Hosts that only poll need no display at all
+    let mut c = Chip8::new((), MockInput::new(), NoopBeeper {}, quirks);
+    c.register[VA] = 62;
+    c.register[VB] = 31;
+    c.load(vec![0xDA, 0xB2, 0xDA, 0xB2, 0x00, 0xE0]).unwrap();
+    assert!(c.take_dirty().is_empty());
+
+    // A sprite past the corner wraps into all four
+    c.cycle().unwrap();
+    assert!(c.framebuffer().is_dirty());
+    assert_eq!(
+        vec![rect(62, 31, 2, 1), rect(62, 0, 2, 1), rect(0, 31, 6, 1), rect(0, 0, 6, 1)],
+        c.take_dirty()
+    );
+    assert!(!c.framebuffer().is_dirty());
+
+    c.quirks.clip = true;
+    c.cycle().unwrap();
+    assert_eq!(vec![rect(62, 31, 2, 1)], c.take_dirty());
+
+    c.cycle().unwrap();
+    assert_eq!(vec![rect(0, 0, 64, 32)], c.take_dirty());
+
+    // Too many rectangles become one for the whole screen
+    for _ in 0..20 {
+        c.register[VA] += 1;
+        c.pc = 0x200;
+        c.cycle().unwrap();
+    }
+    assert_eq!(vec![rect(0, 0, 64, 32)], c.take_dirty());
+}
+
+#[test]
+fn draw_clips() {
+    let mut c = chip8(Quirks::default());
+    c.register[VA] = 62;
+    c.register[VB] = 31;
+    c.address_reg = 0;
+    c.load(vec![0xDA, 0xB2]).unwrap();
+    c.cycle().unwrap();
+
+    assert_ne!(0, c.grid.pixels()[31 * 64 + 62]);
+    assert_ne!(0, c.grid.pixels()[31 * 64 + 63]);
+    assert_eq!(2, c.grid.pixels().iter().filter(|p| **p != 0).count());
+
+    // The starting position still wraps
+    c.register[VA] = 64 + 10;
+    c.register[VB] = 32 + 10;
+    c.grid.clear(0xF);
+    c.vblank_wait = false;
+    c.pc = 0x200;
+    c.cycle().unwrap();
+    assert_ne!(0, c.grid.pixels()[10 * 64 + 10]);
+    assert_ne!(0, c.grid.pixels()[11 * 64 + 10]);
+}
+
+#[test]
+fn display_wait() {
+    let mut c = chip8(Quirks::cosmac_vip());
+    // DRW VA, VB, 1; JP 0x200
+    c.load(vec![0xDA, 0xB1, 0x12, 0x00]).unwrap();
+    c.cycle().unwrap();
+    assert_eq!(0x202, c.pc);
+
+    // Nothing runs until the next tick
+    c.cycle().unwrap();
+    c.cycle().unwrap();
+    assert_eq!(0x202, c.pc);
+    c.tick_timers();
+    c.cycle().unwrap();
+    assert_eq!(0x200, c.pc);
+
+    let mut c = chip8(Quirks::chip48());
+    c.load(vec![0xDA, 0xB1, 0x12, 0x00]).unwrap();
+    c.cycle().unwrap();
+    c.cycle().unwrap();
+    assert_eq!(0x200, c.pc);
+}
+
+#[test]
+fn run_frame() {
+    struct CountingDisplay {
+        draws: ::std::rc::Rc<::std::cell::Cell<usize>>,
+    }
+    impl Display for CountingDisplay {
+        fn clear(&mut self) {}
+
+        fn draw(&mut self, _: &Framebuffer) -> Result<(), String> {
+            self.draws.set(self.draws.get() + 1);
+            Ok(())
+        }
+    }
+
+    let draws = ::std::rc::Rc::new(::std::cell::Cell::new(0));
+    let display = CountingDisplay { draws: draws.clone() };
+    let mut c = Chip8::new(display, MockInput::new(), NoopBeeper {}, Quirks::chip48());
+    c.load(
+        asm::assemble(
+            "
+            LD V0, 30
+            LD DT, V0
+        loop:
+            DRW V1, V1, 1
+            ADD V2, 1
+            SE V2, 15
+            JP loop
+            LD V3, K
+            LD V4, 1
+            EXIT
+    ",
+        ).unwrap(),
+    ).unwrap();
+
+    // Every draw in the frame shows up once, at its end
+    let frame = c.run_frame(10).unwrap();
+    assert_eq!(
+        FrameResult {
+            halted: false,
+            waiting_for_key: false,
+            display_dirty: true,
+        },
+        frame
+    );
+    assert_eq!(10, c.cycles());
+    assert_eq!(1, draws.get());
+    assert_eq!(29, c.delay_timer());
+
+    // Waiting for a key ends the frame; timers keep going while it waits
+    let frame = c.run_frame(100).unwrap();
+    assert!(frame.waiting_for_key);
+    assert_eq!(2 + 4 * 15 - 1 + 1, c.cycles());
+    c.set_key(3, true);
+    assert!(c.run_frame(100).unwrap().waiting_for_key);
+    assert_eq!(27, c.delay_timer());
+    c.set_key(3, false);
+    let frame = c.run_frame(100).unwrap();
+    assert!(frame.halted);
+    assert!(!frame.waiting_for_key);
+    assert!(!frame.display_dirty);
+    assert_eq!(3, c.registers()[V3]);
+    assert_eq!(27, c.delay_timer());
+
+    // Halted for good; timers stop too
+    assert!(c.run_frame(100).unwrap().halted);
+    assert_eq!(27, c.delay_timer());
+}
+
+#[test]
+fn bcd_out_of_range() {
+    let mut c = chip8(Quirks::default());
+    c.register[VA] = 123;
+    c.address_reg = 4094;
+    c.load(vec![0xFA, 0x33]).unwrap();
+    assert_eq!(
+        Err(Chip8Error::MemoryOutOfRange {
+            address: 4096,
+            pc: 0x200,
+        }),
+        c.cycle()
+    );
+}
+
+#[test]
+fn fetch_out_of_range() {
+    let mut c = chip8(Quirks::default());
+    c.pc = 4095;
+    assert_eq!(
+        Err(Chip8Error::MemoryOutOfRange {
+            address: 4096,
+            pc: 4095,
+        }),
+        c.cycle()
+    );
+}
+
 #[test]
 fn bcd() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     c.register[VA] = 123;
     c.address_reg = 2000;
-    c.load(vec![0xFA, 0x33]);
-    c.cycle();
+    c.load(vec![0xFA, 0x33]).unwrap();
+    c.cycle().unwrap();
     assert_eq!(2000, c.address_reg);
 
     assert_eq!(1, c.memory[2000]);
@@ -506,7 +1043,7 @@ fn bcd() {
 
 #[test]
 fn reg_dump() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     for rx2 in 0..0xF {
         c.register[rx2 as usize] = rx2;
     }
@@ -514,10 +1051,10 @@ fn reg_dump() {
         c.pc = 0x200;
         c.address_reg = 2000;
         let start = c.address_reg;
-        c.memory = [0; 4096];
+        c.memory = vec![0; 4096];
 
-        c.load(vec![0xF0 + rx, 0x55]);
-        c.cycle();
+        c.load(vec![0xF0 + rx, 0x55]).unwrap();
+        c.cycle().unwrap();
 
         for x in 0..rx {
             assert_eq!(x, c.memory[start as usize + x as usize]);
@@ -532,17 +1069,17 @@ fn reg_dump() {
 
 #[test]
 fn reg_load() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
+    let mut c = chip8(Quirks::default());
     let start: usize = 2000;
-    for rx2 in 0..0xF as usize {
+    for rx2 in 0..0xF_usize {
         c.memory[rx2 + start] = rx2 as u8
     }
     for rx in 0..0xF {
         c.pc = 0x200;
         c.address_reg = start as u16;
         c.register = [0; 16];
-        c.load(vec![0xF0 + rx, 0x65]);
-        c.cycle();
+        c.load(vec![0xF0 + rx, 0x65]).unwrap();
+        c.cycle().unwrap();
 
         for x in 0..rx as usize {
             assert_eq!(x as u8, c.register[x]);
@@ -556,10 +1093,80 @@ fn reg_load() {
     }
 }
 
+#[test]
+fn reg_dump_load_top_of_memory() {
+    // I wraps round to 0 after the last byte of XO-CHIP's 64 KiB
+    let mut c = chip8(Quirks::xo_chip());
+    for r in 0..16 {
+        c.register[r] = r as u8 + 1;
+    }
+    c.address_reg = 0xFFF0;
+    c.load(vec![0xFF, 0x55, 0xFF, 0x65]).unwrap();
+
+    c.cycle().unwrap();
+    assert_eq!(0, c.address_reg);
+    assert_eq!(1, c.memory[0xFFF0]);
+    assert_eq!(16, c.memory[0xFFFF]);
+
+    c.register = [0; 16];
+    c.address_reg = 0xFFF0;
+    c.cycle().unwrap();
+    assert_eq!(0, c.address_reg);
+    assert_eq!(1, c.register[V0]);
+    assert_eq!(16, c.register[VF]);
+}
+
+#[test]
+fn load_store_quirk() {
+    let mut c = chip8(Quirks::chip48());
+    c.register[V0] = 1;
+    c.register[V1] = 2;
+    c.address_reg = 2000;
+    c.load(vec![0xF1, 0x55, 0xF1, 0x65]).unwrap();
+
+    c.cycle().unwrap();
+    assert_eq!(2000, c.address_reg);
+    assert_eq!(1, c.memory[2000]);
+    assert_eq!(2, c.memory[2001]);
+
+    c.register = [0; 16];
+    c.cycle().unwrap();
+    assert_eq!(2000, c.address_reg);
+    assert_eq!(1, c.register[V0]);
+    assert_eq!(2, c.register[V1]);
+}
+
+#[test]
+fn quirks_from_str() {
+    assert_eq!(Ok(Quirks::cosmac_vip()), "vip".parse());
+    assert_eq!(Ok(Quirks::chip48()), "CHIP48".parse());
+    assert_eq!(Ok(Quirks::superchip()), "schip".parse());
+    assert_eq!(
+        Err(UnknownPreset("foo".to_string())),
+        "foo".parse::<Quirks>()
+    );
+}
+
+#[test]
+fn quirk_flags() {
+    let mut q = Quirks::cosmac_vip();
+    assert!(q.set("clip", false));
+    assert!(q.set("load_store", false));
+    assert!(!q.set("wrap", true));
+    assert!(q.set("index_overflow", true));
+    assert!(q.index_overflow);
+    assert!(!q.clip);
+    assert!(!q.load_store);
+    for (name, value) in Quirks::chip48().flags().iter() {
+        q.set(name, *value);
+    }
+    assert_eq!(Quirks::chip48(), q);
+}
+
 #[test]
 fn rom_load() {
-    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), NoopBeeper {});
-    c.load(vec![0xFF, 0xFF, 0xFF, 0xFF]);
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0xFF, 0xFF, 0xFF, 0xFF]).unwrap();
     for i in 0x200..0x204 {
         assert_eq!(0xFF, c.memory[i]);
     }
@@ -567,3 +1174,1023 @@ fn rom_load() {
         assert_eq!(0, c.memory[i]);
     }
 }
+
+#[test]
+fn hires() {
+    let mut c = chip8(Quirks::superchip());
+    // HIGH; LOW
+    c.load(vec![0x00, 0xFF, 0x00, 0xFE]).unwrap();
+    assert_eq!(64, c.grid.width());
+
+    c.cycle().unwrap();
+    assert!(c.grid.hires());
+    assert_eq!((128, 64), (c.grid.width(), c.grid.height()));
+    assert_eq!(128 * 64, c.grid.pixels().len());
+
+    c.cycle().unwrap();
+    assert!(!c.grid.hires());
+    assert_eq!(64 * 32, c.grid.pixels().len());
+
+    // Switching clears the screen, which frames report like any other change
+    c.pc = 0x200;
+    assert!(c.run_frame(1).unwrap().display_dirty);
+    assert!(c.run_frame(1).unwrap().display_dirty);
+    assert!(!c.run_frame(0).unwrap().display_dirty);
+}
+
+#[test]
+fn exit() {
+    let mut c = chip8(Quirks::superchip());
+    c.load(vec![0x00, 0xFD]).unwrap();
+    assert_eq!(Ok(false), c.cycle());
+}
+
+#[test]
+fn draw_big_sprite() {
+    let mut c = chip8(Quirks::superchip());
+    c.register[VA] = 120;
+    c.register[VB] = 0;
+    c.address_reg = 0x300;
+    for i in 0..32 {
+        c.memory[0x300 + i] = 0xFF;
+    }
+    // HIGH; DRW VA, VB, 0
+    c.load(vec![0x00, 0xFF, 0xDA, 0xB0]).unwrap();
+    c.cycle().unwrap();
+    c.cycle().unwrap();
+
+    // Clipped at the right edge, so only 8 of the 16 columns show
+    assert_eq!(8 * 16, c.grid.pixels().iter().filter(|p| **p != 0).count());
+    assert_ne!(0, c.grid.get(127, 15));
+    assert_eq!(0, c.grid.get(127, 16));
+    assert_eq!(0, c.register[VF]);
+
+    c.pc = 0x202;
+    c.cycle().unwrap();
+    assert_eq!(0, c.grid.pixels().iter().filter(|p| **p != 0).count());
+    assert_eq!(1, c.register[VF]);
+}
+
+#[test]
+fn scroll() {
+    let mut c = chip8(Quirks::superchip());
+    c.grid.toggle(10, 10, 1);
+    // SCD 3; SCR; SCL; SCL
+    c.load(vec![0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC]).unwrap();
+
+    c.cycle().unwrap();
+    assert_ne!(0, c.grid.get(10, 13));
+    assert_eq!(1, c.grid.pixels().iter().filter(|p| **p != 0).count());
+
+    c.cycle().unwrap();
+    assert_ne!(0, c.grid.get(14, 13));
+    assert_eq!(1, c.grid.pixels().iter().filter(|p| **p != 0).count());
+
+    c.cycle().unwrap();
+    c.cycle().unwrap();
+    assert_ne!(0, c.grid.get(6, 13));
+    assert_eq!(1, c.grid.pixels().iter().filter(|p| **p != 0).count());
+
+    // Scrolled off the edge
+    c.grid.clear(0xF);
+    c.grid.toggle(1, 31, 1);
+    c.pc = 0x200;
+    c.cycle().unwrap();
+    assert_eq!(0, c.grid.pixels().iter().filter(|p| **p != 0).count());
+}
+
+#[test]
+fn big_font() {
+    let mut c = chip8(Quirks::superchip());
+    for x in 0x0..0x10 {
+        c.pc = 0x200;
+        c.register[VA] = x;
+        c.load(vec![0xFA, 0x30]).unwrap();
+        c.cycle().unwrap();
+        assert_eq!(80 + x as u16 * 10, c.address_reg);
+
+        for n in 0..10 {
+            assert_ne!(0, c.memory[(c.address_reg + n) as usize]);
+        }
+    }
+}
+
+#[test]
+fn rpl_flags() {
+    let mut c = chip8(Quirks::superchip());
+    for x in 0..16 {
+        c.register[x] = x as u8 + 1;
+    }
+    // LD R, V7; LD V7, R
+    c.load(vec![0xF7, 0x75, 0xF7, 0x85]).unwrap();
+    c.cycle().unwrap();
+    assert_eq!([1, 2, 3, 4, 5, 6, 7, 8], c.rpl[..8]);
+    assert_eq!(0, c.rpl[8]);
+
+    c.register = [0; 16];
+    c.cycle().unwrap();
+    assert_eq!([1, 2, 3, 4, 5, 6, 7, 8], c.register[..8]);
+    assert_eq!(0, c.register[8]);
+}
+
+#[test]
+fn extended_memory() {
+    let mut c = chip8(Quirks::xo_chip());
+    assert_eq!(0x10000, c.memory.len());
+    assert_eq!(
+        Err(Chip8Error::RomTooLarge {
+            size: 0x10000,
+            max: 0x10000 - 512,
+        }),
+        c.load(vec![0; 0x10000])
+    );
+    c.load(vec![0; 0x10000 - 512]).unwrap();
+}
+
+#[test]
+fn long_mem() {
+    let mut c = chip8(Quirks::xo_chip());
+    // LD I, long 0xBEEF
+    c.load(vec![0xF0, 0x00, 0xBE, 0xEF]).unwrap();
+    c.cycle().unwrap();
+    assert_eq!(0xBEEF, c.address_reg);
+    assert_eq!(0x204, c.pc);
+}
+
+#[test]
+fn skip_long() {
+    let mut c = chip8(Quirks::xo_chip());
+    // SE VA, 0; LD I, long 0xBEEF
+    c.load(vec![0x3A, 0x00, 0xF0, 0x00, 0xBE, 0xEF]).unwrap();
+    c.cycle().unwrap();
+    assert_eq!(0x206, c.pc);
+}
+
+#[test]
+fn save_load_range() {
+    let mut c = chip8(Quirks::xo_chip());
+    for x in 0..16 {
+        c.register[x] = x as u8;
+    }
+    c.address_reg = 0x1000;
+    // SAVE V2 - V4; SAVE V4 - V2; LOAD V5 - V7
+    c.load(vec![0x52, 0x42, 0x54, 0x22, 0x55, 0x73]).unwrap();
+
+    c.cycle().unwrap();
+    assert_eq!([2, 3, 4], c.memory[0x1000..0x1003]);
+    assert_eq!(0x1000, c.address_reg);
+
+    c.cycle().unwrap();
+    assert_eq!([4, 3, 2], c.memory[0x1000..0x1003]);
+
+    c.cycle().unwrap();
+    assert_eq!([4, 3, 2], c.register[5..8]);
+    assert_eq!(0x1000, c.address_reg);
+}
+
+#[test]
+fn planes() {
+    let mut c = chip8(Quirks::xo_chip());
+    c.address_reg = 0x300;
+    c.memory[0x300] = 0x80;
+    c.memory[0x301] = 0xC0;
+    // PLANE 2; DRW VA, VB, 1; PLANE 3; DRW VA, VB, 1; PLANE 1; CLS
+    c.load(vec![0xF2, 0x01, 0xDA, 0xB1, 0xF3, 0x01, 0xDA, 0xB1, 0xF1, 0x01, 0x00, 0xE0]).unwrap();
+
+    c.cycle().unwrap();
+    c.cycle().unwrap();
+    assert_eq!(2, c.grid.get(0, 0));
+    assert_eq!(0, c.register[VF]);
+
+    // Both planes, each with its own row of sprite data
+    c.cycle().unwrap();
+    c.cycle().unwrap();
+    assert_eq!(1, c.grid.get(0, 0));
+    assert_eq!(2, c.grid.get(1, 0));
+    assert_eq!(1, c.register[VF]);
+
+    // Clearing only touches the selected plane
+    c.cycle().unwrap();
+    c.cycle().unwrap();
+    assert_eq!(0, c.grid.get(0, 0));
+    assert_eq!(2, c.grid.get(1, 0));
+}
+
+#[test]
+fn scroll_up() {
+    let mut c = chip8(Quirks::xo_chip());
+    c.grid.toggle(10, 10, 1);
+    c.grid.toggle(10, 10, 2);
+    // PLANE 2; SCU 4
+    c.load(vec![0xF2, 0x01, 0x00, 0xD4]).unwrap();
+    c.cycle().unwrap();
+    c.cycle().unwrap();
+    assert_eq!(1, c.grid.get(10, 10));
+    assert_eq!(2, c.grid.get(10, 6));
+}
+
+#[test]
+fn audio_pattern() {
+    let beeper = MockBeeper {
+        on: false,
+        toggles: 0,
+        pattern: None,
+    };
+    let mut c = Chip8::new(NoopDisplay {}, MockInput::new(), beeper, Quirks::xo_chip());
+    c.address_reg = 0x300;
+    for n in 0..16 {
+        c.memory[0x300 + n] = n as u8;
+    }
+    c.register[VA] = 112;
+    // AUDIO; PITCH VA
+    c.load(vec![0xF0, 0x02, 0xFA, 0x3A]).unwrap();
+
+    c.cycle().unwrap();
+    let (pattern, rate) = c.beep.pattern.unwrap();
+    assert_eq!([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15], pattern);
+    assert_eq!(4000.0, rate);
+
+    // An octave up
+    c.cycle().unwrap();
+    let (_, rate) = c.beep.pattern.unwrap();
+    assert!((rate - 8000.0).abs() < 0.01);
+}
+
+#[test]
+fn save_state_roundtrip() {
+    // RND V0, 0xFF; LD I, 0x300; LD [I], V0; JP 0x200
+    let rom = vec![0xC0, 0xFF, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00];
+    let mut c = chip8(Quirks::default());
+    c.seed_rng(7);
+    c.load(rom.clone()).unwrap();
+    c.grid.toggle(3, 4, 1);
+    c.stack.push(0x246);
+    c.delay_timer = 9;
+    c.cycle().unwrap();
+
+    let state = c.save_state();
+    let mut a = Vec::new();
+    for _ in 0..8 {
+        c.cycle().unwrap();
+        a.push(c.register[V0]);
+    }
+
+    // A fresh machine picks up exactly where the first left off
+    let mut d = chip8(Quirks::chip48());
+    d.load(rom).unwrap();
+    d.load_state(&state).unwrap();
+    assert_eq!(Quirks::default(), d.quirks);
+    assert_eq!(0x202, d.pc);
+    assert_eq!(vec![0x246], d.stack);
+    assert_eq!(9, d.delay_timer);
+    assert_eq!(1, d.grid.get(3, 4));
+    let mut b = Vec::new();
+    for _ in 0..8 {
+        d.cycle().unwrap();
+        b.push(d.register[V0]);
+    }
+    assert_eq!(a, b);
+    assert_eq!(c.memory, d.memory);
+
+    // Mostly empty memory compresses well; the fonts are most of it
+    assert!(state.len() < 512);
+}
+
+#[test]
+fn save_state_errors() {
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0x12, 0x00]).unwrap();
+    let state = c.save_state();
+
+    assert_eq!(Err(StateError::BadMagic), c.load_state(b"nope"));
+
+    let mut old = state.clone();
+    old[4] = STATE_VERSION + 1;
+    assert_eq!(
+        Err(StateError::UnsupportedVersion(STATE_VERSION + 1)),
+        c.load_state(&old)
+    );
+
+    assert_eq!(
+        Err(StateError::Truncated),
+        c.load_state(&state[..state.len() - 4])
+    );
+
+    let mut other = chip8(Quirks::default());
+    other.load(vec![0x13, 0x00]).unwrap();
+    assert_eq!(
+        Err(StateError::RomMismatch {
+            expected: rom_hash(&[0x13, 0x00]),
+            found: rom_hash(&[0x12, 0x00]),
+        }),
+        other.load_state(&state)
+    );
+
+    c.load_state(&state).unwrap();
+}
+
+#[test]
+fn save_state_key_wait() {
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0xFA, 0x0A]).unwrap();
+    let idle = c.save_state();
+    let mut r = Rewind::new(4, 1);
+    r.record(&c);
+
+    // FX0A has seen B go down, and takes it once it comes back up
+    c.set_key(0xB, true);
+    c.cycle().unwrap();
+    let latched = c.save_state();
+    c.set_key(0xB, false);
+
+    // A state from before the press forgets the key
+    c.load_state(&idle).unwrap();
+    c.cycle().unwrap();
+    assert_eq!(0x200, c.pc);
+
+    // One from after it finishes the wait, in a fresh machine too
+    let mut d = chip8(Quirks::default());
+    d.load(vec![0xFA, 0x0A]).unwrap();
+    d.load_state(&latched).unwrap();
+    d.cycle().unwrap();
+    assert_eq!(0x202, d.pc);
+    assert_eq!(0xB, d.register[VA]);
+
+    // Rewinding does the same
+    c.load_state(&latched).unwrap();
+    assert!(r.rewind(&mut c));
+    c.cycle().unwrap();
+    assert_eq!(0x200, c.pc);
+}
+
+#[test]
+fn rewind() {
+    // ADD V0, 1; JP 0x200
+    let mut c = chip8(Quirks::default());
+    c.load(vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+    let mut r = Rewind::new(3, 2);
+    assert!(!r.rewind(&mut c));
+
+    for _ in 0..10 {
+        r.record(&c);
+        c.cycle().unwrap();
+        c.cycle().unwrap();
+    }
+    // Snapshots were taken with V0 at 0, 2, 4, 6 and 8; only three remain
+    assert_eq!(3, r.len());
+    assert!(r.rewind(&mut c));
+    assert_eq!(8, c.register[V0]);
+    assert!(r.rewind(&mut c));
+    assert_eq!(6, c.register[V0]);
+    assert!(r.rewind(&mut c));
+    assert_eq!(4, c.register[V0]);
+    assert!(!r.rewind(&mut c));
+    assert_eq!(4, c.register[V0]);
+    assert!(r.is_empty());
+}
+
+#[test]
+fn disasm_text() {
+    let text = |code| disasm::decode(code).to_string();
+    assert_eq!("LD V1, 0x20", text(0x6120));
+    assert_eq!("DRW V0, V1, 5", text(0xD015));
+    assert_eq!("CLS", text(0x00E0));
+    assert_eq!("SCD 4", text(0x00C4));
+    assert_eq!("JP 0x2A0", text(0x12A0));
+    assert_eq!("JP V0, 0x300", text(0xB300));
+    assert_eq!("SE VA, VB", text(0x5AB0));
+    assert_eq!("SAVE V1, V4", text(0x5142));
+    assert_eq!("SHL V3, V3", text(0x833E));
+    assert_eq!("LD VF, [I]", text(0xFF65));
+    assert_eq!("LD HF, V2", text(0xF230));
+    assert_eq!("PLANE 3", text(0xF301));
+    assert_eq!("DW 0xFFFF", text(0xFFFF));
+    assert_eq!(disasm::Instruction::Call(0x456), disasm::decode(0x2456));
+}
+
+#[test]
+fn disasm_rom() {
+    let lines = disasm::disassemble(&[0x61, 0x20, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xEE, 0xAB]);
+    let text: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+    assert_eq!(
+        vec![
+            "0200  6120       LD V1, 0x20",
+            "0202  F000 1234  LD I, LONG 0x1234",
+            "0206  00EE       RET",
+            "0208  AB         DB 0xAB",
+        ],
+        text
+    );
+    assert_eq!(0x202, lines[1].address);
+    assert_eq!(None, lines[3].instruction);
+
+    // An F000 cut off by the end of the ROM has no address to load
+    let lines = disasm::disassemble(&[0xF0, 0x00]);
+    assert_eq!("DW 0xF000", lines[0].instruction.unwrap().to_string());
+}
+
+#[test]
+fn assembled_program() {
+    let mut c = chip8(Quirks::default());
+    let rom = asm::assemble(
+        "
+            LD V0, 3
+        loop:
+            ADD V1, 2
+            ADD V0, -1
+            SE V0, 0
+            JP loop
+        done:
+            JP done
+        ",
+    ).unwrap();
+    c.load(rom).unwrap();
+    while c.pc != 0x20A {
+        c.cycle().unwrap();
+    }
+    assert_eq!(6, c.register[V1]);
+}
+
+const DEBUGGEE: &str = "
+        LD V0, 1        ; 200
+        CALL sub        ; 202
+        LD V2, 3        ; 204
+        LD I, 0x300     ; 206
+        LD [I], V2      ; 208
+        LD V0, [I]      ; 20A
+        EXIT            ; 20C
+    sub:
+        LD V1, 2        ; 20E
+        RET             ; 210
+";
+
+fn debuggee() -> Chip8<NoopDisplay, MockInput, NoopBeeper> {
+    // CHIP-48 leaves I alone on FX55, so FX65 reads back the same bytes
+    let mut c = chip8(Quirks::chip48());
+    c.load(asm::assemble(DEBUGGEE).unwrap()).unwrap();
+    c
+}
+
+#[test]
+fn debugger_breakpoints() {
+    let mut c = debuggee();
+    let mut d = Debugger::new();
+    d.add_breakpoint(0x206);
+    d.add_breakpoint(0x210);
+
+    assert_eq!(Some(Stop::Breakpoint(0x210)), d.run(&mut c, 100).unwrap());
+    assert_eq!(&[0x204], c.stack());
+    assert_eq!(2, c.registers()[V1]);
+
+    // Paused until resumed, which does not hit the same breakpoint again
+    assert_eq!(None, d.run(&mut c, 100).unwrap());
+    assert_eq!(0x210, c.pc());
+    d.resume();
+    assert_eq!(Some(Stop::Breakpoint(0x206)), d.run(&mut c, 100).unwrap());
+    d.remove_breakpoint(0x206);
+    d.resume();
+    assert_eq!(Some(Stop::Halted), d.run(&mut c, 100).unwrap());
+    assert_eq!(0x300, c.address_reg());
+}
+
+#[test]
+fn debugger_stepping() {
+    let mut c = debuggee();
+    let mut d = Debugger::new();
+    assert_eq!(Some(Stop::Step), d.step_into(&mut c).unwrap());
+    assert!(!d.is_running());
+
+    // Into the call, then back out
+    d.step_into(&mut c).unwrap();
+    assert_eq!(0x20E, c.pc());
+    d.run_until_return(&c);
+    assert_eq!(Some(Stop::Step), d.run(&mut c, 100).unwrap());
+    assert_eq!(0x204, c.pc());
+
+    // Over the call in one go
+    let mut c = debuggee();
+    d.step_into(&mut c).unwrap();
+    d.step_over(&c);
+    assert_eq!(Some(Stop::Step), d.run(&mut c, 100).unwrap());
+    assert_eq!(0x204, c.pc());
+    assert_eq!(2, c.registers()[V1]);
+
+    // Anything else steps a single instruction
+    d.step_over(&c);
+    assert_eq!(Some(Stop::Step), d.run(&mut c, 100).unwrap());
+    assert_eq!(0x206, c.pc());
+}
+
+#[test]
+fn debugger_watchpoints() {
+    let mut c = debuggee();
+    let mut d = Debugger::new();
+    d.add_watchpoint(Watchpoint::Register(Register::V(V2)));
+    d.add_watchpoint(Watchpoint::Write(0x302));
+    d.add_watchpoint(Watchpoint::Read(0x300));
+    assert_eq!(3, d.watchpoints().len());
+
+    let stop = |watchpoint, pc| Some(Stop::Watchpoint { watchpoint, pc });
+    assert_eq!(
+        stop(Watchpoint::Register(Register::V(V2)), 0x204),
+        d.run(&mut c, 100).unwrap()
+    );
+    d.resume();
+    assert_eq!(stop(Watchpoint::Write(0x302), 0x208), d.run(&mut c, 100).unwrap());
+    d.resume();
+    assert_eq!(stop(Watchpoint::Read(0x300), 0x20A), d.run(&mut c, 100).unwrap());
+    d.resume();
+    assert_eq!(Some(Stop::Halted), d.run(&mut c, 100).unwrap());
+}
+
+/// A GDB client talking to a stub over a real socket
+struct GdbClient {
+    stub: gdb::GdbStub,
+    stream: std::net::TcpStream,
+}
+
+impl GdbClient {
+    fn attach(c: &mut Chip8<NoopDisplay, MockInput, NoopBeeper>) -> Self {
+        let mut stub = gdb::GdbStub::bind(0).unwrap();
+        let stream = std::net::TcpStream::connect(stub.local_addr().unwrap()).unwrap();
+        stream.set_nodelay(true).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_millis(200)))
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        stub.poll(c).unwrap();
+        GdbClient { stub, stream }
+    }
+
+    /// Send `packet` and return the reply, if the stub sends one now
+    fn ask(
+        &mut self,
+        c: &mut Chip8<NoopDisplay, MockInput, NoopBeeper>,
+        packet: &str,
+    ) -> Option<String> {
+        use std::io::Write;
+
+        let sum = packet.bytes().fold(0u8, |s, b| s.wrapping_add(b));
+        let framed = format!("${}#{:02x}", packet, sum);
+        self.stream.write_all(framed.as_bytes()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        self.stub.poll(c).unwrap();
+        self.reply()
+    }
+
+    /// The next reply, skipping acks
+    fn reply(&mut self) -> Option<String> {
+        use std::io::Read;
+
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        while self.stream.read(&mut byte).is_ok() {
+            reply.push(byte[0]);
+            let n = reply.len();
+            if n > 3 && reply[n - 3] == b'#' {
+                let text = String::from_utf8(reply).unwrap();
+                let start = text.find('$').unwrap();
+                return Some(text[start + 1..n - 3].to_string());
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn gdb_stub() {
+    let mut c = debuggee();
+    let mut gdb = GdbClient::attach(&mut c);
+    assert!(gdb.stub.is_attached());
+    assert!(gdb.stub.is_paused());
+    let reply = |s: &str| Some(s.to_string());
+
+    assert_eq!(reply("S05"), gdb.ask(&mut c, "?"));
+    let xml = gdb.ask(&mut c, "qXfer:features:read:target.xml:0,9").unwrap();
+    assert_eq!("m<?xml ver", xml);
+    let xml = gdb.ask(&mut c, "qXfer:features:read:target.xml:0,fff").unwrap();
+    assert!(xml.starts_with('l'));
+    assert!(xml.contains("<architecture>z80</architecture>"));
+
+    // PC is register 0x11, little-endian
+    assert_eq!(reply("0002"), gdb.ask(&mut c, "p11"));
+    assert_eq!(reply("6001220e"), gdb.ask(&mut c, "m200,4"));
+    assert_eq!(reply("OK"), gdb.ask(&mut c, "M300,2:beef"));
+    assert_eq!(&[0xBE, 0xEF], &c.memory()[0x300..0x302]);
+    assert_eq!(reply("E01"), gdb.ask(&mut c, "m1000,1"));
+    assert_eq!(reply("E01"), gdb.ask(&mut c, "mffffffffffffffff,2"));
+    assert_eq!(reply("E01"), gdb.ask(&mut c, "Mffffffffffffffff,2:beef"));
+    assert_eq!(reply("OK"), gdb.ask(&mut c, "P5=2a"));
+    assert_eq!(0x2A, c.registers()[V5]);
+
+    // Stepping and continuing reply once execution stops
+    assert_eq!(None, gdb.ask(&mut c, "s"));
+    gdb.stub.run(&mut c, 10).unwrap();
+    assert_eq!(reply("S05"), gdb.reply());
+    assert_eq!(0x202, c.pc());
+
+    assert_eq!(reply("OK"), gdb.ask(&mut c, "Z0,206,2"));
+    assert_eq!(None, gdb.ask(&mut c, "c"));
+    gdb.stub.run(&mut c, 10).unwrap();
+    assert_eq!(reply("S05"), gdb.reply());
+    assert_eq!(0x206, c.pc());
+
+    let g = gdb.ask(&mut c, "g").unwrap();
+    assert_eq!(2 * (16 + 2 + 2 + 3), g.len());
+    assert_eq!("010203", &g[..6]);
+
+    // A bad G changes no registers at all
+    assert_eq!(reply("E01"), gdb.ask(&mut c, &format!("Gff{}", &g[2..g.len() - 2])));
+    assert_eq!(reply("E01"), gdb.ask(&mut c, &format!("Gff{}zz", &g[2..g.len() - 2])));
+    assert_eq!(0x01, c.registers()[V0]);
+    assert_eq!(reply("OK"), gdb.ask(&mut c, &format!("Gff{}", &g[2..])));
+    assert_eq!(0xFF, c.registers()[V0]);
+
+    assert_eq!(reply("OK"), gdb.ask(&mut c, "z0,206,2"));
+    assert_eq!(None, gdb.ask(&mut c, "c"));
+    assert!(!gdb.stub.run(&mut c, 10).unwrap());
+    assert_eq!(reply("W00"), gdb.reply());
+}
+
+/// A trace sink the test can still read after handing it to the machine
+#[derive(Clone)]
+struct SharedBuffer(::std::rc::Rc<::std::cell::RefCell<Vec<u8>>>);
+
+impl ::std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuffer {
+    fn lines(&self) -> Vec<String> {
+        let text = String::from_utf8(self.0.borrow().clone()).unwrap();
+        text.lines().map(|l| l.to_string()).collect()
+    }
+}
+
+#[test]
+fn trace() {
+    let mut c = debuggee();
+    let out = SharedBuffer(Default::default());
+    c.set_trace(Some(Trace::new(out.clone())));
+    while c.cycle().unwrap() {}
+    assert_eq!(9, c.cycles());
+
+    let lines = out.lines();
+    assert_eq!(9, lines.len());
+    assert_eq!(
+        "00000000 0200 6001 LD V0, 0x01          \
+         V 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 I 0000 SP 00 DT 00 ST 00",
+        lines[0]
+    );
+    assert!(lines[3].starts_with("00000003 0210 00EE RET "));
+    assert!(lines[3].contains(" V 01 02 00 "));
+    assert!(lines[3].ends_with(" SP 01 DT 00 ST 00"));
+
+    // Cycles outside the range are counted but not written
+    let mut c = debuggee();
+    let out = SharedBuffer(Default::default());
+    let range = "0x204-20a".parse().unwrap();
+    c.set_trace(Some(Trace::new(out.clone()).range(range)));
+    while c.cycle().unwrap() {}
+    let lines = out.lines();
+    let pcs: Vec<&str> = lines.iter().map(|l| &l[9..13]).collect();
+    assert_eq!(vec!["0204", "0206", "0208", "020A"], pcs);
+    assert!(lines[0].starts_with("00000004 "));
+
+    // A deep call stack keeps the columns lined up
+    let mut c = debuggee();
+    let shallow = c.trace_line();
+    c.stack = vec![0x202; 17];
+    let deep = c.trace_line();
+    assert_eq!(shallow.len(), deep.len());
+    assert!(deep.ends_with(" SP 11 DT 00 ST 00"));
+
+    assert_eq!(Err(InvalidRange("2ff-200".to_string())), "2ff-200".parse::<AddressRange>());
+    assert!("200".parse::<AddressRange>().is_err());
+}
+
+#[test]
+fn screen_images() {
+    let mut c = chip8(Quirks::default());
+    // The font's 1 at (1, 0), then the same on plane 2 at (2, 1)
+    let program = "LD V0, 1\nLD F, V0\nDRW V0, V2, 5\nADD V0, 1\nADD V2, 1\nPLANE 2\nDRW V0, V2, 5";
+    c.load(asm::assemble(program).unwrap()).unwrap();
+    for _ in 0..7 {
+        c.cycle().unwrap();
+        c.tick_timers();
+    }
+
+    let art = image::ascii(c.framebuffer());
+    let lines: Vec<&str> = art.lines().collect();
+    assert_eq!(32, lines.len());
+    assert_eq!("...#....", &lines[0][..8]);
+    assert_eq!("..##2...", &lines[1][..8]);
+    assert_eq!("...222..", &lines[5][..8]);
+
+    let pbm = image::pbm(c.framebuffer(), 2);
+    assert!(pbm.starts_with(b"P4\n128 64\n"));
+    assert_eq!(10 + 16 * 64, pbm.len());
+    assert_eq!(0b0000_0011, pbm[10]);
+
+    let png = image::png(c.framebuffer(), &image::PALETTE, 1);
+    assert_eq!(b"\x89PNG\r\n\x1a\n", &png[..8]);
+    assert_eq!(b"IHDR", &png[12..16]);
+    assert_eq!(&[0, 0, 0, 64, 0, 0, 0, 32, 8, 3], &png[16..26]);
+    assert_eq!(b"IEND\xae\x42\x60\x82", &png[png.len() - 8..]);
+}
+
+#[test]
+fn palette_presets() {
+    assert_eq!(Some(image::PALETTE), image::palette("default"));
+    let amber = image::palette("Amber").unwrap();
+    assert_eq!((0xFF, 0xB0, 0x00), amber[1]);
+    assert_eq!(image::PALETTE[4..], amber[4..]);
+    assert!(image::PALETTE_NAMES.iter().all(|name| image::palette(name).is_some()));
+    assert_eq!(None, image::palette("sepia"));
+}
+
+#[test]
+fn scripted_input() {
+    use headless::{KeyPress, ScriptedInput};
+
+    let press = |s: &str| s.parse::<KeyPress>();
+    assert_eq!(Ok(KeyPress { frame: 60, key: 0xA, frames: 1 }), press("60:a"));
+    assert_eq!(Ok(KeyPress { frame: 0, key: 5, frames: 30 }), press("0:5:30"));
+    assert!(press("60:10").is_err());
+    assert!(press("60:1:0").is_err());
+    assert!(press("60").is_err());
+
+    let mut input = ScriptedInput::new(vec![press("10:2:5").unwrap(), press("3:1").unwrap()]);
+    assert_eq!(0, input.keys());
+    input.set_frame(3);
+    assert_eq!(0b10, input.keys());
+    input.set_frame(14);
+    assert_eq!(0b100, input.keys());
+    input.set_key(0xF, true);
+    assert_eq!(0x8004, input.keys());
+
+    assert!(!input.is_finished());
+    input.set_frame(15);
+    assert!(input.is_finished());
+}
+
+#[test]
+fn sha1_digest() {
+    let hex = |data: &[u8]| -> String {
+        sha1(data).iter().map(|b| format!("{:02x}", b)).collect()
+    };
+    assert_eq!("da39a3ee5e6b4b0d3255bfef95601890afd80709", hex(b""));
+    assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", hex(b"abc"));
+    // Two blocks once padded
+    assert_eq!(
+        "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+        hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")
+    );
+}
+
+#[test]
+fn synth_envelope() {
+    use sound::{Synth, Tone, Waveform};
+
+    assert_eq!(Ok(Waveform::Sine), "Sine".parse());
+    assert!("saw".parse::<Waveform>().is_err());
+
+    // 10 samples of attack and release at 1000 samples per second
+    let tone = Tone {
+        waveform: Waveform::Square,
+        pitch: 100.0,
+        volume: 0.5,
+        attack: 0.01,
+        release: 0.01,
+    };
+    let mut synth = Synth::new(tone, 1000);
+    let mut out = [1.0; 20];
+    synth.fill(&mut out);
+    assert!(synth.is_silent());
+    assert_eq!([0.0; 20], out);
+
+    synth.set_on(true);
+    synth.fill(&mut out);
+    assert!((out[0] - 0.05).abs() < 1e-6);
+    assert!((out[4] - 0.25).abs() < 1e-6);
+    assert!(out[9..].iter().all(|&x| (x.abs() - 0.5).abs() < 1e-6));
+    // Half of each 10 sample period is high
+    assert!(out[4] > 0.0 && out[5] < 0.0);
+
+    synth.set_on(false);
+    synth.fill(&mut out);
+    assert!(out[0].abs() < 0.5 && out[0] != 0.0);
+    assert!(out[9..].iter().all(|&x| x == 0.0));
+    assert!(synth.is_silent());
+}
+
+#[test]
+fn synth_waveforms() {
+    use sound::{Synth, Tone, Waveform};
+
+    let wave = |waveform| {
+        let tone = Tone { waveform, pitch: 250.0, volume: 1.0, attack: 0.0, release: 0.0 };
+        let mut synth = Synth::new(tone, 1000);
+        synth.set_on(true);
+        let mut out = [0.0; 8];
+        synth.fill(&mut out);
+        out
+    };
+    let near = |a: [f32; 8], b: [f32; 8]| a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-6);
+    assert!(near([1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, -1.0], wave(Waveform::Square)));
+    assert!(near([-1.0, 0.0, 1.0, 0.0, -1.0, 0.0, 1.0, 0.0], wave(Waveform::Triangle)));
+    assert!(near([0.0, 1.0, 0.0, -1.0, 0.0, 1.0, 0.0, -1.0], wave(Waveform::Sine)));
+    // Noise holds a level for each period
+    let noise = wave(Waveform::Noise);
+    assert_eq!(noise[0], noise[3]);
+    assert_eq!(noise[4], noise[7]);
+    assert!(noise[3] != noise[4]);
+
+    // A pattern replaces the waveform
+    let tone = Tone { attack: 0.0, release: 0.0, ..Tone::default() };
+    let mut synth = Synth::new(tone, 1000);
+    synth.set_pattern(&[0xA0; 16], 1000.0);
+    synth.set_on(true);
+    let mut out = [0.0; 4];
+    synth.fill(&mut out);
+    assert_eq!([0.2, -0.2, 0.2, -0.2], out);
+}
+
+#[test]
+fn wav_recording() {
+    use sound::{Tone, WavBeeper, Waveform};
+    use std::io::Cursor;
+
+    // 10 samples per frame, without fades
+    let tone = Tone {
+        waveform: Waveform::Square,
+        pitch: 60.0,
+        volume: 0.5,
+        attack: 0.0,
+        release: 0.0,
+    };
+    let wav = WavBeeper::new(Cursor::new(Vec::new()), tone, 600, ()).unwrap();
+    let mut c = Chip8::new((), MockInput { keys: 0 }, wav, Quirks::default());
+    // V0 = 2; ST = V0; loop
+    c.load(vec![0x60, 0x02, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+    for _ in 0..4 {
+        c.run_frame(2).unwrap();
+    }
+    let wav = c.beeper_mut();
+    wav.finish().unwrap();
+    assert_eq!(40, wav.samples());
+
+    let data = wav.get_ref().get_ref();
+    assert_eq!(44 + 80, data.len());
+    assert_eq!(b"RIFF", &data[..4]);
+    assert_eq!(&(36u32 + 80).to_le_bytes(), &data[4..8]);
+    assert_eq!(b"WAVEfmt ", &data[8..16]);
+    assert_eq!(&600u32.to_le_bytes(), &data[24..28]);
+    assert_eq!(b"data", &data[36..40]);
+    assert_eq!(&80u32.to_le_bytes(), &data[40..44]);
+
+    // FX18 runs halfway through the first frame, and the sound timer runs
+    // out at the end of the second
+    let samples: Vec<i16> = data[44..]
+        .chunks(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    assert!(samples[..5].iter().all(|&s| s == 0));
+    assert!(samples[5..20].iter().all(|&s| s.abs() == 16383));
+    assert!(samples[20..].iter().all(|&s| s == 0));
+}
+
+/// Decode a GIF LZW code stream, as decoders do
+fn unlzw(data: &[u8], min_size: u8) -> Vec<u8> {
+    let clear = 1usize << min_size;
+    let end = clear + 1;
+    let fresh = || -> Vec<Vec<u8>> {
+        let mut table: Vec<Vec<u8>> = (0..clear).map(|i| vec![i as u8]).collect();
+        table.push(Vec::new());
+        table.push(Vec::new());
+        table
+    };
+    let mut table = fresh();
+    let mut size = min_size + 1;
+    let mut prev: Option<Vec<u8>> = None;
+    let mut out = Vec::new();
+    let mut pos = 0;
+    loop {
+        let mut code = 0;
+        for i in 0..size as usize {
+            let bit = data[(pos + i) / 8] >> ((pos + i) % 8) & 1;
+            code |= (bit as usize) << i;
+        }
+        pos += size as usize;
+        if code == clear {
+            table = fresh();
+            size = min_size + 1;
+            prev = None;
+            continue;
+        }
+        if code == end {
+            return out;
+        }
+        let entry = match (code < table.len(), &prev) {
+            (true, _) => table[code].clone(),
+            (false, Some(p)) if code == table.len() => {
+                let mut e = p.clone();
+                e.push(p[0]);
+                e
+            }
+            _ => panic!("bad code {} at bit {}", code, pos),
+        };
+        out.extend_from_slice(&entry);
+        if let Some(mut p) = prev.take() {
+            if table.len() < 4096 {
+                p.push(entry[0]);
+                table.push(p);
+                if table.len() == 1 << size && size < 12 {
+                    size += 1;
+                }
+            }
+        }
+        prev = Some(entry);
+    }
+}
+
+#[test]
+fn gif_lzw() {
+    let mut rng = Xorshift::new(1);
+    // Long enough to fill the table and start it again, and runs long
+    // enough to build long strings
+    let noise: Vec<u8> = (0..100_000).map(|_| rng.next_u8() & 0xF).collect();
+    let runs: Vec<u8> = (0..100_000).map(|i| (i / 777 % 3) as u8).collect();
+    for data in &[vec![], vec![7], vec![1, 1, 1, 1, 1, 1, 1], noise, runs] {
+        assert_eq!(*data, unlzw(&image::lzw(data, 4), 4));
+    }
+}
+
+#[test]
+fn gif_recording() {
+    use record::Recorder;
+
+    let path = ::std::env::temp_dir().join(format!("chip8-test-{}.gif", ::std::process::id()));
+    let mut recorder = Recorder::create(&path, &image::PALETTE, 1).unwrap();
+    let mut grid = Framebuffer::new();
+    // Blank for 3 frames, then a pixel for 3, then flickering every frame
+    for frame in 0..12 {
+        if frame == 3 || frame >= 6 {
+            grid.toggle(0, 0, 1);
+        }
+        recorder.frame(&grid).unwrap();
+    }
+    recorder.finish().unwrap();
+    let gif = ::std::fs::read(&path).unwrap();
+    ::std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(b"GIF89a", &gif[..6]);
+    assert_eq!(&[128, 0, 64, 0, 0xF3], &gif[6..11]);
+    assert_eq!(b"NETSCAPE2.0", &gif[64..75]);
+
+    // Walk the blocks, collecting each frame's delay and pixels
+    let mut frames = Vec::new();
+    let mut pos = 80;
+    let mut delay = 0;
+    loop {
+        match gif[pos] {
+            0x21 => {
+                assert_eq!(&[0xF9, 4], &gif[pos + 1..pos + 3]);
+                delay = u16::from_le_bytes([gif[pos + 4], gif[pos + 5]]);
+                pos += 8;
+            }
+            0x2C => {
+                let min_size = gif[pos + 10];
+                pos += 11;
+                let mut data = Vec::new();
+                while gif[pos] != 0 {
+                    let len = gif[pos] as usize;
+                    data.extend_from_slice(&gif[pos + 1..pos + 1 + len]);
+                    pos += 1 + len;
+                }
+                pos += 1;
+                frames.push((delay, unlzw(&data, min_size)));
+            }
+            0x3B => break,
+            b => panic!("unexpected block {:02X}", b),
+        }
+    }
+
+    // Lo-res pixels are doubled to fill the hi-res size
+    let lit = |pixels: &Vec<u8>| pixels[0] == 1 && pixels[129] == 1 && pixels[2] == 0;
+    assert!(frames.iter().all(|f| f.1.len() == 128 * 64));
+    assert!(frames[0].1.iter().all(|&p| p == 0));
+    assert!(lit(&frames[1].1));
+    // 12 frames are 20 hundredths of a second, and flickering frames are
+    // merged to be at least 2 long
+    assert_eq!(5, frames[0].0);
+    assert_eq!(5, frames[1].0);
+    assert!(frames[2..].iter().all(|f| f.0 >= 2));
+    assert_eq!(20, frames.iter().map(|f| f.0).sum::<u16>());
+}
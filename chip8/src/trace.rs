@@ -0,0 +1,127 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use super::{Beeper, Chip8, Display, Input};
+use disasm;
+
+/// An inclusive range of addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl AddressRange {
+    pub fn contains(&self, address: u16) -> bool {
+        self.start <= address && address <= self.end
+    }
+}
+
+/// Returned when parsing an address range that is not `start-end` in hex
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRange(pub String);
+
+impl fmt::Display for InvalidRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid address range '{}', expected start-end in hex, e.g. 200-2FF",
+            self.0
+        )
+    }
+}
+
+impl FromStr for AddressRange {
+    type Err = InvalidRange;
+
+    /// Parse `start-end`, both in hex with an optional `0x`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = |s: &str| {
+            let s = s.trim();
+            let s = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+            u16::from_str_radix(s, 16).ok()
+        };
+        let mut parts = s.splitn(2, '-');
+        match (parts.next().and_then(&hex), parts.next().and_then(&hex)) {
+            (Some(start), Some(end)) if start <= end => Ok(AddressRange { start, end }),
+            _ => Err(InvalidRange(s.to_string())),
+        }
+    }
+}
+
+/// Where `Chip8` writes a line per instruction executed, and for which
+/// addresses. Each line shows the machine just before the instruction runs:
+///
+/// ```text
+/// 00000007 0206 A300 LD I, 0x300          V 01 02 03 00 00 00 00 00 00 00 00 00 00 00 00 00 I 0000 SP 00 DT 00 ST 00
+/// ```
+///
+/// that is the count of instructions executed before it, PC, the opcode and
+/// its mnemonic, V0-VF, I, the call depth and the two timers. Every field
+/// has a fixed width, so traces from two runs can be compared with `diff`
+pub struct Trace {
+    out: Box<dyn Write>,
+    range: Option<AddressRange>,
+}
+
+impl Trace {
+    pub fn new<W: Write + 'static>(out: W) -> Self {
+        Trace {
+            out: Box::new(out),
+            range: None,
+        }
+    }
+
+    /// Only trace instructions within `range`
+    pub fn range(mut self, range: AddressRange) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    pub(crate) fn wants(&self, pc: u16) -> bool {
+        self.range.is_none_or(|r| r.contains(pc))
+    }
+
+    pub(crate) fn write_line(&mut self, line: &str) -> ::std::io::Result<()> {
+        writeln!(self.out, "{}", line)
+    }
+}
+
+impl<D: Display, I: Input, B: Beeper> Chip8<D, I, B> {
+    /// Write a line to `trace` for each instruction from now on, or stop
+    /// tracing with None
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+
+    /// Instructions executed since the machine was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The trace line for the instruction at PC
+    pub(crate) fn trace_line(&self) -> String {
+        let pc = self.pc as usize;
+        let end = (pc + 4).min(self.memory.len());
+        let (opcode, text) = match disasm::disassemble(&self.memory[pc.min(end)..end]).first() {
+            Some(line) if line.bytes.len() > 1 => {
+                ((line.bytes[0] as u16) << 8 | line.bytes[1] as u16, line.text())
+            }
+            _ => (0, "??".to_string()),
+        };
+        let registers: Vec<String> = self.register.iter().map(|v| format!("{:02X}", v)).collect();
+        format!(
+            "{:08} {:04X} {:04X} {:<20} V {} I {:04X} SP {:02X} DT {:02X} ST {:02X}",
+            self.cycles,
+            self.pc,
+            opcode,
+            text,
+            registers.join(" "),
+            self.address_reg,
+            self.stack.len(),
+            self.delay_timer,
+            self.sound_timer
+        )
+    }
+}
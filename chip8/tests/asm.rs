@@ -0,0 +1,124 @@
+extern crate chip8;
+
+use std::env;
+use std::fs;
+
+use chip8::asm::{assemble, assemble_file, AsmError};
+use chip8::disasm;
+
+fn error_at(source: &str) -> (usize, usize, String) {
+    match assemble(source).unwrap_err() {
+        AsmError::Source { line, column, message, .. } => (line, column, message),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn instructions() {
+    let rom = assemble(
+        "CLS\n\
+         ld v1, 0x20\n\
+         DRW V0, V1, 5\n\
+         LD I, 0x300\n\
+         LD I, LONG 0x1234\n\
+         LD [I], VF\n\
+         SHR V3\n\
+         JP V0, 0x400",
+    ).unwrap();
+    assert_eq!(
+        vec![
+            0x00, 0xE0, 0x61, 0x20, 0xD0, 0x15, 0xA3, 0x00, 0xF0, 0x00, 0x12, 0x34, 0xFF, 0x55,
+            0x83, 0x36, 0xB4, 0x00,
+        ],
+        rom
+    );
+}
+
+#[test]
+fn labels_constants_and_data() {
+    let rom = assemble(
+        "; a comment\n\
+         HEIGHT EQU SIZE - 1\n\
+         SIZE EQU 6\n\
+         start: LD I, sprite\n\
+         DRW V0, V0, HEIGHT ; draw it\n\
+         loop:\n\
+         JP loop\n\
+         sprite: DB 0xF0, 0b10010000, -1\n\
+         DW start + 2",
+    ).unwrap();
+    assert_eq!(
+        vec![0xA2, 0x06, 0xD0, 0x05, 0x12, 0x04, 0xF0, 0x90, 0xFF, 0x02, 0x02],
+        rom
+    );
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        (2, 4, "Undefined name 'nowhere'".to_string()),
+        error_at("CLS\nJP nowhere")
+    );
+    assert_eq!(
+        (1, 1, "Unknown instruction 'MOV'".to_string()),
+        error_at("MOV V1, V2")
+    );
+    assert_eq!(
+        (1, 1, "Invalid operands for SKP".to_string()),
+        error_at("SKP 3")
+    );
+    assert_eq!(
+        (1, 8, "Value 256 does not fit in a byte".to_string()),
+        error_at("LD V1, 256")
+    );
+    assert_eq!(
+        (2, 1, "'a' is already defined".to_string()),
+        error_at("a: CLS\na: CLS")
+    );
+    assert_eq!((1, 7, "Missing operand".to_string()), error_at("ADD V1,"));
+    assert_eq!(
+        (1, 7, "'A' is defined in terms of itself".to_string()),
+        error_at("A EQU A\nDB A")
+    );
+    assert_eq!(
+        "Line 1, column 4: Unexpected character '$'",
+        assemble("JP $200").unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn includes() {
+    let dir = env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("main.asm"), "CALL draw\nEXIT\nINCLUDE \"lib/draw.asm\"\n").unwrap();
+    fs::write(dir.join("lib/draw.asm"), "draw: DRW V0, V1, 1\nRET\nJP oops\n").unwrap();
+
+    match assemble_file(dir.join("main.asm")).unwrap_err() {
+        AsmError::Source { file, line, column, .. } => {
+            assert_eq!(Some(dir.join("lib/draw.asm")), file);
+            assert_eq!((3, 4), (line, column));
+        }
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    fs::write(dir.join("lib/draw.asm"), "draw: DRW V0, V1, 1\nRET\n").unwrap();
+    assert_eq!(
+        vec![0x22, 0x04, 0x00, 0xFD, 0xD0, 0x11, 0x00, 0xEE],
+        assemble_file(dir.join("main.asm")).unwrap()
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disassembly_round_trips() {
+    // Every possible word, valid instruction or not
+    for code in 0..=0xFFFFu16 {
+        let rom = vec![(code >> 8) as u8, code as u8];
+        let text: Vec<String> = disasm::disassemble(&rom).iter().map(|l| l.text()).collect();
+        assert_eq!(rom, assemble(&text.join("\n")).unwrap(), "{}", text[0]);
+    }
+
+    let rom = vec![0xF0, 0x00, 0xBE, 0xEF, 0x61, 0x20, 0x7F];
+    let text: Vec<String> = disasm::disassemble(&rom).iter().map(|l| l.text()).collect();
+    assert_eq!(rom, assemble(&text.join("\n")).unwrap());
+}
@@ -1,64 +1,109 @@
 extern crate sdl2;
 
+use std::fs::File;
+use std::io::{self, BufWriter};
 use sdl2::AudioSubsystem;
 use sdl2::audio::{AudioDevice, AudioCallback, AudioSpecDesired};
 use chip8::Beeper;
+use chip8::sound::{Synth, Tone, WavBeeper};
 
-pub struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+pub struct SynthCallback {
+    synth: Synth,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for SynthCallback {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
+        self.synth.fill(out);
     }
 }
 
+/// Plays the beeper through SDL. The device runs all the time and the synth
+/// fades the tone in and out, as pausing the device mid-wave clicks
 pub struct SdlBeeper {
-    device: AudioDevice<SquareWave>,
+    device: AudioDevice<SynthCallback>,
+    on: bool,
+    muted: bool,
+
+    /// A recording of the beeper, which muting leaves alone
+    wav: Option<WavBeeper<BufWriter<File>>>,
 }
 
 impl Beeper for SdlBeeper {
     fn beep_on(&mut self) {
-        self.device.resume();
+        self.on = true;
+        self.update();
+        self.wav.beep_on();
     }
 
     fn beep_off(&mut self) {
-        self.device.pause();
+        self.on = false;
+        self.update();
+        self.wav.beep_off();
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], rate: f32) {
+        self.device.lock().synth.set_pattern(pattern, rate);
+        self.wav.set_pattern(pattern, rate);
+    }
+
+    fn advance_to(&mut self, seconds: f64) {
+        self.wav.advance_to(seconds);
     }
 }
 
 impl SdlBeeper {
-    pub fn new(audio_subsystem: AudioSubsystem) -> Self {
+    pub fn new(audio_subsystem: AudioSubsystem, tone: Tone) -> Self {
         let desired_spec = AudioSpecDesired {
             freq: Some(44100),
             channels: Some(1), // mono
             samples: None, // default sample size
         };
 
+        let device = audio_subsystem
+            .open_playback(None, &desired_spec, |spec| {
+                SynthCallback { synth: Synth::new(tone, spec.freq as u32) }
+            })
+            .unwrap();
+        device.resume();
         SdlBeeper {
-            device: audio_subsystem
-                .open_playback(None, &desired_spec, |spec| {
-                    // initialize the audio callback
-                    SquareWave {
-                        phase_inc: 440.0 / spec.freq as f32,
-                        phase: 0.0,
-                        volume: 0.20,
-                    }
-                })
-                .unwrap(),
+            device,
+            on: false,
+            muted: false,
+            wav: None,
         }
     }
+
+    /// Record the beeper, with the same tone, to a WAV file at `path`
+    pub fn record(&mut self, path: &str) -> io::Result<()> {
+        let rate = self.device.spec().freq as u32;
+        let tone = self.device.lock().synth.tone();
+        let file = BufWriter::new(File::create(path)?);
+        self.wav = Some(WavBeeper::new(file, tone, rate, ())?);
+        Ok(())
+    }
+
+    /// Finish the recording, if there is one
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.wav.take() {
+            Some(mut wav) => wav.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Silence the beeper, or let it sound again, fading either way
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let sounding = self.on && !self.muted;
+        self.device.lock().synth.set_on(sounding);
+    }
 }
@@ -0,0 +1,17 @@
+extern crate chip8;
+
+use std::io::{self, Write};
+use chip8::Beeper;
+
+/// Rings the terminal bell each time a sound starts. Terminals have no way
+/// to hold a tone, so the length of the sound is lost
+pub struct BellBeeper;
+
+impl Beeper for BellBeeper {
+    fn beep_on(&mut self) {
+        let mut out = io::stdout();
+        let _ = out.write_all(b"\x07").and_then(|_| out.flush());
+    }
+
+    fn beep_off(&mut self) {}
+}
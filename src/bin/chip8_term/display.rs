@@ -0,0 +1,179 @@
+extern crate chip8;
+extern crate termion;
+
+use std::io::{self, Stdout, Write};
+use std::string::String;
+use chip8::Framebuffer;
+use termion::cursor;
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::AlternateScreen;
+
+/// How screen pixels map onto terminal characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Glyphs {
+    /// `▀`, `▄` and `█`: a column of two pixels per character
+    HalfBlock,
+    /// Braille dots: two columns of four pixels per character, for small
+    /// terminals
+    Braille,
+}
+
+impl Glyphs {
+    /// Pixels covered by one character, across and down
+    fn cell(self) -> (usize, usize) {
+        match self {
+            Glyphs::HalfBlock => (1, 2),
+            Glyphs::Braille => (2, 4),
+        }
+    }
+
+    /// The character for the cell whose top left pixel is at `x`, `y`. Any
+    /// lit plane lights the pixel
+    fn at(self, grid: &Framebuffer, x: usize, y: usize) -> char {
+        self.glyph(|dx, dy| {
+            x + dx < grid.width() && y + dy < grid.height() && grid.get(x + dx, y + dy) != 0
+        })
+    }
+
+    /// The character for a cell, given whether the pixel `dx`, `dy` from
+    /// its top left is lit
+    fn glyph<F: Fn(usize, usize) -> bool>(self, lit: F) -> char {
+        match self {
+            Glyphs::HalfBlock => {
+                match (lit(0, 0), lit(0, 1)) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                }
+            }
+            Glyphs::Braille => {
+                // Dot numbering runs down the left column, then the right,
+                // with the bottom row's dots added last
+                const DOTS: [(usize, usize, u32); 8] = [
+                    (0, 0, 0x01),
+                    (0, 1, 0x02),
+                    (0, 2, 0x04),
+                    (1, 0, 0x08),
+                    (1, 1, 0x10),
+                    (1, 2, 0x20),
+                    (0, 3, 0x40),
+                    (1, 3, 0x80),
+                ];
+                let bits = DOTS.iter()
+                    .filter(|&&(dx, dy, _)| lit(dx, dy))
+                    .fold(0, |acc, &(_, _, bit)| acc | bit);
+                ::std::char::from_u32(0x2800 + bits).unwrap()
+            }
+        }
+    }
+}
+
+/// Draws the screen on a raw-mode terminal, switching to the alternate
+/// screen for as long as it lives. Only the characters that changed since
+/// the last draw are written, which keeps redraws cheap over SSH
+pub struct TermDisplay {
+    out: AlternateScreen<RawTerminal<Stdout>>,
+    glyphs: Glyphs,
+
+    /// What is on the terminal now, row-major, with its width in
+    /// characters; empty until the first draw
+    shown: Vec<char>,
+    columns: usize,
+}
+
+impl TermDisplay {
+    pub fn new(glyphs: Glyphs) -> io::Result<Self> {
+        let raw = io::stdout().into_raw_mode()?;
+        let mut out = AlternateScreen::from(raw);
+        write!(out, "{}{}", termion::clear::All, cursor::Hide)?;
+        out.flush()?;
+        Ok(TermDisplay {
+            out,
+            glyphs,
+            shown: Vec::new(),
+            columns: 0,
+        })
+    }
+
+    fn write(&mut self, grid: &Framebuffer) -> io::Result<()> {
+        let (cw, ch) = self.glyphs.cell();
+        let columns = grid.width() / cw;
+        let rows = grid.height() / ch;
+        let cells: Vec<char> = (0..rows * columns)
+            .map(|i| self.glyphs.at(grid, i % columns * cw, i / columns * ch))
+            .collect();
+
+        // A change of resolution redraws everything
+        if columns != self.columns || cells.len() != self.shown.len() {
+            write!(self.out, "{}", termion::clear::All)?;
+            self.shown = vec!['\0'; cells.len()];
+            self.columns = columns;
+        }
+
+        let mut out = String::new();
+        let mut cursor_at = None;
+        for (i, (&new, old)) in cells.iter().zip(self.shown.iter_mut()).enumerate() {
+            if new == *old {
+                continue;
+            }
+            // Runs of changed characters need only one cursor move
+            if cursor_at != Some(i) {
+                let (x, y) = (i % columns, i / columns);
+                out.push_str(&cursor::Goto(x as u16 + 1, y as u16 + 1).to_string());
+            }
+            out.push(new);
+            *old = new;
+            cursor_at = if (i + 1) % columns == 0 { None } else { Some(i + 1) };
+        }
+        if !out.is_empty() {
+            self.out.write_all(out.as_bytes())?;
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TermDisplay {
+    fn drop(&mut self) {
+        let _ = write!(self.out, "{}", cursor::Show);
+        let _ = self.out.flush();
+    }
+}
+
+impl chip8::Display for TermDisplay {
+    fn draw(&mut self, grid: &Framebuffer) -> Result<(), String> {
+        self.write(grid).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_blocks() {
+        let glyph = |top, bottom| Glyphs::HalfBlock.glyph(|_, dy| [top, bottom][dy]);
+        assert_eq!(' ', glyph(false, false));
+        assert_eq!('▀', glyph(true, false));
+        assert_eq!('▄', glyph(false, true));
+        assert_eq!('█', glyph(true, true));
+    }
+
+    #[test]
+    fn braille() {
+        assert_eq!('⠀', Glyphs::Braille.glyph(|_, _| false));
+        assert_eq!('⣿', Glyphs::Braille.glyph(|_, _| true));
+        assert_eq!('⠁', Glyphs::Braille.glyph(|dx, dy| (dx, dy) == (0, 0)));
+        assert_eq!('⠇', Glyphs::Braille.glyph(|dx, dy| dx == 0 && dy < 3));
+        assert_eq!('⢀', Glyphs::Braille.glyph(|dx, dy| (dx, dy) == (1, 3)));
+    }
+
+    #[test]
+    fn off_the_screen() {
+        // Cells hanging over the edge treat the missing pixels as unlit
+        let grid = Framebuffer::new();
+        assert_eq!(' ', Glyphs::HalfBlock.at(&grid, grid.width() - 1, grid.height() - 1));
+        assert_eq!('⠀', Glyphs::Braille.at(&grid, grid.width() - 1, 0));
+    }
+}
@@ -0,0 +1,140 @@
+extern crate chip8;
+extern crate termion;
+
+use std::io::Read;
+use termion::AsyncReader;
+
+/// Terminals report key presses but never releases, so a press holds its
+/// key down for this many frames. That is long enough to bridge the gaps
+/// between the terminal's key repeats
+const HOLD_FRAMES: u64 = 10;
+
+/// What the bytes read from the terminal mean
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    /// Escape or Ctrl-C
+    Quit,
+    Keypad(u8),
+}
+
+/// The CHIP-8 key `byte` presses on `chip8::QWERTY_KEYPAD`, in either case
+fn keypad(byte: u8) -> Option<u8> {
+    chip8::QWERTY_KEYPAD
+        .iter()
+        .find(|&&(c, _)| c as u8 == byte.to_ascii_lowercase())
+        .map(|&(_, key)| key)
+}
+
+/// Decode raw terminal input. Escape sequences, such as those sent by the
+/// arrow keys, are skipped; an escape on its own quits
+fn keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            0x03 => keys.push(Key::Quit),
+            0x1B => {
+                match bytes.get(i + 1) {
+                    Some(&b'[') | Some(&b'O') => {
+                        // Parameters, then a final byte in 0x40-0x7E
+                        i += 2;
+                        while i < bytes.len() && !(0x40..=0x7E).contains(&bytes[i]) {
+                            i += 1;
+                        }
+                    }
+                    _ => keys.push(Key::Quit),
+                }
+            }
+            byte => {
+                if let Some(key) = keypad(byte) {
+                    keys.push(Key::Keypad(key));
+                }
+            }
+        }
+        i += 1;
+    }
+    keys
+}
+
+/// Keypad state read from the terminal, which `TermDisplay` must have put
+/// in raw mode
+pub struct TermInput {
+    stdin: AsyncReader,
+    frame: u64,
+
+    /// The frame at which each key is let go
+    released_at: [u64; 16],
+}
+
+impl TermInput {
+    pub fn new() -> Self {
+        TermInput {
+            stdin: termion::async_stdin(),
+            frame: 0,
+            released_at: [0; 16],
+        }
+    }
+
+    /// Move on to the next frame, taking in any keys typed since the last.
+    /// Returns false if the user asked to quit
+    pub fn poll(&mut self) -> bool {
+        self.frame += 1;
+        !self.read().contains(&Key::Quit)
+    }
+
+    fn read(&mut self) -> Vec<Key> {
+        let mut bytes = Vec::new();
+        let _ = self.stdin.read_to_end(&mut bytes);
+        let keys = keys(&bytes);
+        for key in &keys {
+            if let Key::Keypad(k) = *key {
+                self.released_at[k as usize] = self.frame + HOLD_FRAMES;
+            }
+        }
+        keys
+    }
+}
+
+impl chip8::Input for TermInput {
+    fn keys(&self) -> u16 {
+        (0..16)
+            .filter(|&k| self.frame < self.released_at[k])
+            .fold(0, |keys, k| keys | 1 << k)
+    }
+
+    /// A press from the host is held until released, rather than for
+    /// `HOLD_FRAMES`
+    fn set_key(&mut self, key: u8, pressed: bool) {
+        self.released_at[key as usize] = if pressed { u64::MAX } else { 0 };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keypad_layout() {
+        assert_eq!(Some(0x1), keypad(b'1'));
+        assert_eq!(Some(0xC), keypad(b'4'));
+        assert_eq!(Some(0x4), keypad(b'q'));
+        assert_eq!(Some(0x4), keypad(b'Q'));
+        assert_eq!(Some(0x0), keypad(b'x'));
+        assert_eq!(Some(0xF), keypad(b'V'));
+        assert_eq!(None, keypad(b'5'));
+        assert_eq!(None, keypad(b' '));
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(vec![Key::Keypad(0x5), Key::Keypad(0x7)], keys(b"wA"));
+        assert_eq!(vec![Key::Quit], keys(b"\x03"));
+        assert_eq!(vec![Key::Keypad(0x5), Key::Quit], keys(b"w\x1b"));
+
+        // Arrows and function keys are skipped, along with their parameters
+        assert_eq!(vec![Key::Keypad(0x6)], keys(b"\x1b[A\x1b[15;2~e"));
+        assert_eq!(vec![Key::Keypad(0x8)], keys(b"\x1bOPs"));
+        assert_eq!(Vec::<Key>::new(), keys(b"\x1b[1;5"));
+        assert_eq!(Vec::<Key>::new(), keys(b"hjkl"));
+    }
+}
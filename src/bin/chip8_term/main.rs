@@ -0,0 +1,35 @@
+//! A frontend for the terminal, for playing over SSH. The screen is drawn
+//! with Unicode half blocks or braille, the keypad is the 1234/QWER/ASDF/ZXCV
+//! block of the keyboard, and sound rings the bell. Escape or Ctrl-C quits.
+//!
+//! termion only supports Unix terminals, so elsewhere this just says so
+
+#[cfg(unix)]
+extern crate chip8;
+#[cfg(unix)]
+extern crate structopt;
+#[cfg(unix)]
+#[macro_use]
+extern crate structopt_derive;
+#[cfg(unix)]
+extern crate termion;
+
+#[cfg(unix)]
+mod audio;
+#[cfg(unix)]
+mod display;
+#[cfg(unix)]
+mod input;
+#[cfg(unix)]
+mod term;
+
+#[cfg(unix)]
+pub fn main() {
+    term::main()
+}
+
+#[cfg(not(unix))]
+pub fn main() {
+    eprintln!("chip8_term needs a Unix terminal");
+    std::process::exit(1);
+}
@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::io::Read;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+use chip8::{self, Chip8, Quirks};
+use audio::BellBeeper;
+use display::{Glyphs, TermDisplay};
+use input::TermInput;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "chip8_term", about = "Run a CHIP-8 program in the terminal.")]
+struct Opt {
+    #[structopt(help = "file")]
+    file: String,
+
+    #[structopt(long = "quirks", help = "quirks preset: vip, chip48, schip or xochip",
+                default_value = "vip")]
+    quirks: Quirks,
+
+    #[structopt(long = "seed", help = "random number seed, for reproducible runs")]
+    seed: Option<u64>,
+
+    #[structopt(long = "ipf", help = "instructions to execute per 60 Hz frame [default: 10]")]
+    ipf: Option<usize>,
+
+    #[structopt(long = "hz", help = "instructions to execute per second, in place of --ipf")]
+    hz: Option<usize>,
+
+    #[structopt(long = "braille", help = "draw with braille dots, for small terminals")]
+    braille: bool,
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+pub fn main() {
+    let opt = Opt::from_args();
+    let ipf = match (opt.ipf, opt.hz) {
+        (Some(ipf), _) => ipf,
+        (None, Some(hz)) => (hz / chip8::TIMER_HZ as usize).max(1),
+        (None, None) => chip8::DEFAULT_IPF,
+    };
+    let mut rom = Vec::new();
+    if let Err(e) = File::open(&opt.file).and_then(|mut f| f.read_to_end(&mut rom)) {
+        fail(&format!("Cannot read {}: {}", opt.file, e));
+    }
+
+    let glyphs = if opt.braille { Glyphs::Braille } else { Glyphs::HalfBlock };
+    let display = match TermDisplay::new(glyphs) {
+        Ok(display) => display,
+        Err(e) => fail(&format!("Cannot set up the terminal: {}", e)),
+    };
+    let mut c = Chip8::new(display, TermInput::new(), BellBeeper, opt.quirks);
+    if let Some(seed) = opt.seed {
+        c.seed_rng(seed);
+    }
+    if let Err(e) = c.load(rom) {
+        drop(c);
+        fail(&e.to_string());
+    }
+
+    let frame_time = Duration::new(0, 1_000_000_000u32 / chip8::TIMER_HZ);
+    let mut next_frame = Instant::now();
+    loop {
+        next_frame += frame_time;
+        let now = Instant::now();
+        if next_frame > now {
+            ::std::thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+
+        if !c.input_mut().poll() {
+            break;
+        }
+        match c.run_frame(ipf) {
+            Ok(frame) if frame.halted => break,
+            Ok(_) => (),
+            Err(e) => {
+                // Put the terminal back before reporting
+                drop(c);
+                fail(&e.to_string());
+            }
+        }
+    }
+}
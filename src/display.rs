@@ -1,61 +1,184 @@
 extern crate sdl2;
 extern crate chip8;
 
+use std::collections::BTreeMap;
 use sdl2::render::WindowCanvas;
 use sdl2::rect::Rect;
 use sdl2::pixels::Color;
 use std::string::String;
+use chip8::Framebuffer;
+use chip8::image::PALETTE;
 
 pub struct SdlDisplay {
-    xmult: i16,
-    ymult: i16,
     canvas: WindowCanvas,
+    palette: [(u8, u8, u8); 16],
+
+    /// Frames a pixel takes to fade out once switched off; 1 switches it
+    /// off at once
+    decay: u8,
+
+    /// For each pixel, the plane mask it last lit with and the frames left
+    /// before it has faded out, counting down from `decay`
+    glow: Vec<(u8, u8)>,
+    fading: bool,
 }
 
 impl SdlDisplay {
     pub fn new(mut canvas: WindowCanvas) -> Self {
-        let size = canvas.output_size().unwrap();
-        canvas.set_draw_color(Color::RGB(255, 255, 255));
+        let (r, g, b) = PALETTE[0];
+        canvas.set_draw_color(Color::RGB(r, g, b));
         canvas.clear();
         canvas.present();
         SdlDisplay {
-            xmult: size.0 as i16 / 64,
-            ymult: size.1 as i16 / 32,
-            canvas: canvas,
+            canvas,
+            palette: PALETTE,
+            decay: 1,
+            glow: Vec::new(),
+            fading: false,
         }
     }
-    pub fn present(&mut self) {
-        self.canvas.present()
+
+    pub fn palette(&self) -> &[(u8, u8, u8); 16] {
+        &self.palette
     }
-}
 
-impl chip8::Display for SdlDisplay {
-    fn clear(&mut self) {
-        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
-        self.canvas.clear();
+    /// Use `colors` for the first planes' colors, keeping the default
+    /// palette for any beyond them
+    pub fn set_palette(&mut self, colors: &[(u8, u8, u8)]) {
+        self.palette = PALETTE;
+        for (c, &color) in self.palette.iter_mut().zip(colors) {
+            *c = color;
+        }
     }
 
-    fn draw(&mut self, grid: &[bool; 32 * 64]) -> Result<(), String> {
-        for (i, b) in grid.iter().enumerate() {
-            let y: i32 = (i as i32 / 64) * self.xmult as i32;
-            let x: i32 = (i as i32 % 64) * self.ymult as i32;
-            let r = {
-                let rect = Rect::new(x, y, 4 * self.xmult as u32, self.ymult as u32);
-                if *b {
-                    self.canvas.set_draw_color(Color::RGB(255, 255, 255))
-                } else {
-                    self.canvas.set_draw_color(Color::RGB(0, 0, 0))
-                }
-                self.canvas.fill_rect(rect)
-            };
+    /// Fade pixels out over `frames` frames once they switch off, as the
+    /// phosphor of a CRT would, which hides the flicker of sprites being
+    /// erased and drawn again
+    pub fn set_phosphor(&mut self, frames: u8) {
+        self.decay = frames.max(1);
+        self.glow.clear();
+    }
 
-            match r {
-                Ok(_) => (),
-                Err(_) => return r,
+    /// Whether pixels are still fading, so the screen needs presenting even
+    /// though it has not changed
+    pub fn is_fading(&self) -> bool {
+        self.fading
+    }
+
+    /// Draw the whole screen and show it. Pixels are batched by color, so
+    /// this costs a handful of calls into SDL however busy the screen is
+    pub fn present(&mut self, grid: &Framebuffer) -> Result<(), String> {
+        // The scale changes when the program switches resolution
+        let size = self.canvas.output_size()?;
+        let xmult = size.0 / grid.width() as u32;
+        let ymult = size.1 / grid.height() as u32;
+
+        if self.glow.len() != grid.pixels().len() {
+            self.glow = vec![(0, 0); grid.pixels().len()];
+        }
+        let mut by_color = BTreeMap::new();
+        self.fading = false;
+        for (i, (&pixel, glow)) in grid.pixels().iter().zip(self.glow.iter_mut()).enumerate() {
+            let color = fade(glow, pixel, self.decay, &self.palette);
+            self.fading |= pixel & 0xF == 0 && glow.1 > 0;
+            let color = match color {
+                Some(color) => color,
+                None => continue,
             };
+            let y = (i / grid.width()) as u32 * ymult;
+            let x = (i % grid.width()) as u32 * xmult;
+            by_color
+                .entry(color)
+                .or_insert_with(Vec::new)
+                .push(Rect::new(x as i32, y as i32, xmult, ymult));
         }
 
-        self.present();
+        let (r, g, b) = self.palette[0];
+        self.canvas.set_draw_color(Color::RGB(r, g, b));
+        self.canvas.clear();
+        for (&(r, g, b), rects) in &by_color {
+            self.canvas.set_draw_color(Color::RGB(r, g, b));
+            self.canvas.fill_rects(rects)?;
+        }
+        self.canvas.present();
         Ok(())
     }
 }
+
+/// Take a pixel through a frame in which its planes are `pixel`, returning
+/// the color to draw it in, or None if it shows the background. `glow` is
+/// the plane mask it last lit with and the frames left before it has faded
+/// out; lit pixels glow for `decay` frames, so 0 or 1 switches them off at
+/// once
+fn fade(
+    glow: &mut (u8, u8),
+    pixel: u8,
+    decay: u8,
+    palette: &[(u8, u8, u8); 16],
+) -> Option<(u8, u8, u8)> {
+    let decay = decay.max(1);
+    if pixel & 0xF != 0 {
+        *glow = (pixel & 0xF, decay);
+    } else {
+        glow.1 = glow.1.saturating_sub(1);
+    }
+    match *glow {
+        (_, 0) => None,
+        (mask, left) => Some(blend(palette[0], palette[mask as usize], left as u32, decay as u32)),
+    }
+}
+
+/// The color `amount` `of` the way from `from` to `to`
+fn blend(from: (u8, u8, u8), to: (u8, u8, u8), amount: u32, of: u32) -> (u8, u8, u8) {
+    let mix = |a: u8, b: u8| ((a as u32 * (of - amount) + b as u32 * amount) / of) as u8;
+    (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> [(u8, u8, u8); 16] {
+        let mut palette = PALETTE;
+        palette[0] = (0, 0, 0);
+        palette[1] = (200, 100, 40);
+        palette
+    }
+
+    /// The colors a pixel shows over the frames it is lit in `frames`
+    fn colors(decay: u8, frames: &[u8]) -> Vec<Option<(u8, u8, u8)>> {
+        let mut glow = (0, 0);
+        frames.iter().map(|&pixel| fade(&mut glow, pixel, decay, &palette())).collect()
+    }
+
+    #[test]
+    fn fades_to_background() {
+        assert_eq!(
+            vec![
+                Some((200, 100, 40)),
+                Some((150, 75, 30)),
+                Some((100, 50, 20)),
+                Some((50, 25, 10)),
+                None,
+                None,
+            ],
+            colors(4, &[1, 0, 0, 0, 0, 0])
+        );
+
+        // Lighting it again mid-fade brings it back at full strength
+        assert_eq!(
+            vec![Some((200, 100, 40)), Some((100, 50, 20)), Some((200, 100, 40))],
+            colors(2, &[1, 0, 1])
+        );
+    }
+
+    #[test]
+    fn no_decay_switches_off_at_once() {
+        for &decay in &[0, 1] {
+            assert_eq!(
+                vec![None, Some((200, 100, 40)), Some((200, 100, 40)), None],
+                colors(decay, &[0, 1, 1, 0])
+            );
+        }
+    }
+}
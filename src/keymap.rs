@@ -0,0 +1,152 @@
+extern crate sdl2;
+extern crate chip8;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use sdl2::keyboard::Keycode;
+
+/// Which host keys press which CHIP-8 keys. Any number of host keys can
+/// press the same CHIP-8 key.
+///
+/// Mappings are written one per line, as the CHIP-8 key in hex and the SDL
+/// names of the host keys for it:
+///
+/// ```text
+/// # Arrows as well as WASD
+/// 5 = W, Up
+/// 7 = A, Left
+/// ```
+///
+/// A mapping replaces every host key that pressed its CHIP-8 key before
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    keys: HashMap<Keycode, u8>,
+
+    /// Every mapping applied on top of the default layout, in order
+    mappings: Vec<String>,
+}
+
+impl Keymap {
+    /// The CHIP-8 key `key` presses, if any
+    pub fn get(&self, key: Keycode) -> Option<u8> {
+        self.keys.get(&key).cloned()
+    }
+
+    /// The mappings applied so far, which recreate this keymap when applied
+    /// to the default one
+    pub fn mappings(&self) -> &[String] {
+        &self.mappings
+    }
+
+    /// Apply a single mapping, `<key> = <host key>[, <host key>...]`
+    pub fn set(&mut self, mapping: &str) -> Result<(), String> {
+        let mut parts = mapping.splitn(2, '=');
+        let (key, hosts) = match (parts.next(), parts.next()) {
+            (Some(key), Some(hosts)) => (key.trim(), hosts),
+            _ => return Err(format!("Expected <key> = <host keys> in '{}'", mapping)),
+        };
+        let key = match u8::from_str_radix(key, 16) {
+            Ok(k) if k < 16 => k,
+            _ => return Err(format!("'{}' is not a CHIP-8 key, 0 to F", key)),
+        };
+        let mut codes = Vec::new();
+        for name in hosts.split(',').map(str::trim) {
+            match Keycode::from_name(name) {
+                Some(code) => codes.push(code),
+                None => return Err(format!("Unknown key name '{}'", name)),
+            }
+        }
+
+        self.keys.retain(|_, k| *k != key);
+        for code in codes {
+            self.keys.insert(code, key);
+        }
+        self.mappings.push(mapping.trim().to_string());
+        Ok(())
+    }
+
+    /// Apply every mapping in `text`, skipping blank lines and `#` comments
+    pub fn load(&mut self, text: &str) -> Result<(), String> {
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if !line.is_empty() {
+                self.set(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Could not read {}: {}", path, e))?;
+        self.load(&text).map_err(|e| format!("{}, {}", path, e))
+    }
+}
+
+impl Default for Keymap {
+    /// `chip8::QWERTY_KEYPAD`, with SDL's keycode for each character
+    fn default() -> Self {
+        let keys = chip8::QWERTY_KEYPAD
+            .iter()
+            .map(|&(c, key)| (Keycode::from_i32(c as i32).unwrap(), key))
+            .collect();
+        Keymap {
+            keys,
+            mappings: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set() {
+        let mut keymap = Keymap::default();
+        assert_eq!(Some(0x5), keymap.get(Keycode::W));
+        keymap.set(" 5 = Up, K ").unwrap();
+        assert_eq!(None, keymap.get(Keycode::W));
+        assert_eq!(Some(0x5), keymap.get(Keycode::Up));
+        assert_eq!(Some(0x5), keymap.get(Keycode::K));
+        assert_eq!(&["5 = Up, K".to_string()], keymap.mappings());
+    }
+
+    #[test]
+    fn load() {
+        let mut keymap = Keymap::default();
+        keymap.load("# Arrows\n\n5 = Up  # and W\na = Left\n").unwrap();
+        assert_eq!(Some(0x5), keymap.get(Keycode::Up));
+        assert_eq!(Some(0xA), keymap.get(Keycode::Left));
+        assert_eq!(None, keymap.get(Keycode::Z));
+
+        // The mappings recreate the keymap from the default one
+        let mut again = Keymap::default();
+        for mapping in keymap.mappings() {
+            again.set(mapping).unwrap();
+        }
+        assert_eq!(keymap.keys, again.keys);
+    }
+
+    #[test]
+    fn errors() {
+        let mut keymap = Keymap::default();
+        let err = |keymap: &mut Keymap, mapping| keymap.set(mapping).unwrap_err();
+        assert_eq!("Expected <key> = <host keys> in 'W'", err(&mut keymap, "W"));
+        assert_eq!("'G' is not a CHIP-8 key, 0 to F", err(&mut keymap, "G = W"));
+        assert_eq!("'10' is not a CHIP-8 key, 0 to F", err(&mut keymap, "10 = W"));
+        assert_eq!("Unknown key name 'Nope'", err(&mut keymap, "1 = Up, Nope"));
+        assert_eq!(
+            Err("line 2: Unknown key name 'Nope'".to_string()),
+            keymap.load("1 = 1\n1 = Nope")
+        );
+
+        // A bad mapping changes nothing
+        assert_eq!(Some(0x1), keymap.get(Keycode::Num1));
+        assert_eq!(None, keymap.get(Keycode::Up));
+        assert_eq!(&["1 = 1".to_string()], keymap.mappings());
+    }
+}
@@ -4,23 +4,29 @@ extern crate structopt;
 #[macro_use]
 extern crate structopt_derive;
 
-use std::sync::Arc;
-use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use structopt::StructOpt;
 
-use chip8::Chip8;
+use chip8::{AddressRange, Chip8, Quirks, Rewind, Trace, UnknownPreset};
+use chip8::gdb::GdbStub;
+use chip8::record::Recorder;
+use chip8::sound::{Tone, Waveform};
 mod audio;
 mod display;
 mod input;
+mod keymap;
+mod profile;
 
 use audio::SdlBeeper;
 use display::SdlDisplay;
 use input::SdlInput;
+use keymap::Keymap;
+use profile::Profiles;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "example", about = "An example of StructOpt usage.")]
@@ -34,10 +40,130 @@ struct Opt {
 
     #[structopt(help = "y resolution")]
     y: Option<u32>,
+
+    #[structopt(long = "quirks",
+                help = "quirks preset: vip, chip48, schip or xochip [default: vip]")]
+    quirks: Option<String>,
+
+    #[structopt(long = "seed", help = "random number seed, for reproducible runs")]
+    seed: Option<u64>,
+
+    #[structopt(long = "gdb", help = "listen for a GDB remote debugger on this localhost port")]
+    gdb: Option<u16>,
+
+    #[structopt(long = "trace", help = "write a line per instruction executed to this file")]
+    trace: Option<String>,
+
+    #[structopt(long = "trace-range",
+                help = "only trace instructions in this address range, e.g. 200-2FF")]
+    trace_range: Option<AddressRange>,
+
+    #[structopt(long = "ipf", help = "instructions to execute per 60 Hz frame [default: 10]")]
+    ipf: Option<usize>,
+
+    #[structopt(long = "hz", help = "instructions to execute per second, in place of --ipf")]
+    hz: Option<usize>,
+
+    #[structopt(long = "keymap", help = "file of key mappings, such as '5 = W, Up' per line")]
+    keymap: Option<String>,
+
+    #[structopt(long = "map", help = "key mapping, such as '5=W,Up', after any --keymap")]
+    map: Vec<String>,
+
+    #[structopt(long = "wave",
+                help = "beeper waveform: square, triangle, sine or noise [default: square]")]
+    wave: Option<Waveform>,
+
+    #[structopt(long = "pitch", help = "beeper pitch in Hz [default: 440]")]
+    pitch: Option<f32>,
+
+    #[structopt(long = "volume", help = "beeper volume, from 0 to 1 [default: 0.2]")]
+    volume: Option<f32>,
+
+    #[structopt(long = "attack", help = "milliseconds the beeper takes to fade in [default: 5]")]
+    attack: Option<f32>,
+
+    #[structopt(long = "release",
+                help = "milliseconds the beeper takes to fade out [default: 5]")]
+    release: Option<f32>,
+
+    #[structopt(long = "mute", help = "start with the sound off; F8 toggles it")]
+    mute: bool,
+
+    #[structopt(long = "wav", help = "record the sound, in emulated time, to this WAV file")]
+    wav: Option<String>,
+
+    #[structopt(long = "palette", help = "colors: default, amber, green or lcd")]
+    palette: Option<String>,
+
+    #[structopt(long = "fg", help = "color of lit pixels, as RRGGBB in hex")]
+    fg: Option<String>,
+
+    #[structopt(long = "bg", help = "background color, as RRGGBB in hex")]
+    bg: Option<String>,
+
+    #[structopt(long = "phosphor",
+                help = "frames pixels take to fade out once off, to hide flicker [default: 1]")]
+    phosphor: Option<u8>,
+
+    #[structopt(long = "record",
+                help = "record the screen to a GIF, or to a directory of PNGs; F10 toggles \
+                        recording")]
+    record: Option<String>,
+
+    #[structopt(long = "profiles",
+                help = "ROM profile database [default: ~/.config/chip8/profiles.toml]")]
+    profiles: Option<String>,
+
+    #[structopt(long = "save-profile",
+                help = "save the settings in effect as this ROM's profile")]
+    save_profile: bool,
+
+    #[structopt(long = "title", help = "title to save the ROM's profile under")]
+    title: Option<String>,
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+/// Hi-res pixels are this big in recordings, which matches the default window
+const RECORD_SCALE: usize = 4;
+
+/// Where F10 records to: `<rom>-<n>.gif` for the first n not yet taken
+fn recording_path(rom: &str) -> String {
+    (1..)
+        .map(|n| format!("{}-{}.gif", rom, n))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
+fn start_recording(path: &str, palette: &[(u8, u8, u8); 16]) -> Option<Recorder> {
+    match Recorder::create(path, palette, RECORD_SCALE) {
+        Ok(recorder) => {
+            println!("Recording to {}", path);
+            Some(recorder)
+        }
+        Err(e) => {
+            eprintln!("Could not record to {}: {}", path, e);
+            None
+        }
+    }
+}
+
+fn stop_recording(recorder: Option<Recorder>) {
+    if let Some(recorder) = recorder {
+        let frames = recorder.frames();
+        match recorder.finish() {
+            Ok(()) => println!("Recorded {} frames", frames),
+            Err(e) => eprintln!("Could not finish the recording: {}", e),
+        }
+    }
 }
 
 fn load_rom(f: String) -> std::io::Result<Vec<u8>> {
-    let mut f = File::open(f).unwrap();
+    let mut f = File::open(f)?;
     let mut buf = Vec::new();
     match f.read_to_end(&mut buf) {
         Ok(_) => Ok(buf),
@@ -47,18 +173,96 @@ fn load_rom(f: String) -> std::io::Result<Vec<u8>> {
 
 pub fn main() {
     let opt = Opt::from_args();
-    let xres = {
-        match opt.x {
-            Some(n) => n,
-            None => 512,
+    let xres = opt.x.unwrap_or(512);
+    let yres = opt.y.unwrap_or(256);
+    let rom = load_rom(opt.file.clone()).unwrap();
+
+    // Settings come from the command line, then the ROM's profile, then the
+    // defaults
+    let hash = profile::rom_sha1(&rom);
+    let path = opt.profiles.as_ref().map(PathBuf::from).unwrap_or_else(Profiles::default_path);
+    let mut profiles = Profiles::open(path).unwrap_or_else(|e| fail(&e));
+    let mut profile = profiles.get(&hash).cloned().unwrap_or_default();
+    if let Some(ref title) = profile.title {
+        println!("Profile: {}", title);
+    }
+
+    let platform = opt.quirks
+        .clone()
+        .or_else(|| profile.platform.clone())
+        .unwrap_or_else(|| "vip".to_string());
+    let preset: Quirks = platform.parse().unwrap_or_else(|e: UnknownPreset| fail(&e.to_string()));
+    let mut quirks = preset;
+    // A preset on the command line replaces the profile's quirks as well
+    if opt.quirks.is_none() {
+        for &(ref name, value) in &profile.quirks {
+            quirks.set(name, value);
         }
+    }
+
+    let mut keymap = Keymap::default();
+    let mapped = profile.keymap
+        .iter()
+        .try_for_each(|m| keymap.set(m))
+        .map_err(|e| format!("Profile keymap: {}", e))
+        .and_then(|_| match opt.keymap {
+            Some(ref path) => keymap.load_file(path),
+            None => Ok(()),
+        });
+    if let Err(e) = mapped.and_then(|_| opt.map.iter().try_for_each(|m| keymap.set(m))) {
+        fail(&e);
+    }
+    let ipf = match (opt.ipf, opt.hz, profile.ipf) {
+        (Some(ipf), _, _) => ipf,
+        (None, Some(hz), _) => (hz / chip8::TIMER_HZ as usize).max(1),
+        (None, None, Some(ipf)) => ipf,
+        (None, None, None) => chip8::DEFAULT_IPF,
     };
-    let yres = {
-        match opt.y {
-            Some(n) => n,
-            None => 256,
+
+    let mut palette = chip8::image::PALETTE;
+    palette[..profile.palette.len()].copy_from_slice(&profile.palette);
+    if let Some(ref name) = opt.palette {
+        palette = chip8::image::palette(name).unwrap_or_else(|| {
+            let names = chip8::image::PALETTE_NAMES.join(", ");
+            fail(&format!("Unknown palette '{}', expected one of {}", name, names))
+        });
+    }
+    for &(color, index) in &[(&opt.bg, 0), (&opt.fg, 1)] {
+        if let Some(ref color) = *color {
+            palette[index] = profile::parse_color(color).unwrap_or_else(|e| fail(&e));
         }
-    };
+    }
+
+    if opt.save_profile {
+        let stem = Path::new(&opt.file).file_stem().map(|s| s.to_string_lossy().into_owned());
+        profile.title = opt.title.clone().or(profile.title).or(stem);
+        profile.platform = Some(platform);
+        profile.ipf = Some(ipf);
+        profile.quirks = quirks.flags()
+            .iter()
+            .zip(preset.flags().iter())
+            .filter(|&(flag, base)| flag.1 != base.1)
+            .map(|(&(name, value), _)| (name.to_string(), value))
+            .collect();
+        profile.keymap = keymap.mappings().to_vec();
+        profile.palette = profile::changed_colors(&palette);
+        profiles.set(&hash, profile.clone());
+        profiles.save().unwrap_or_else(|e| fail(&e));
+        println!("Saved the profile for {} to {}",
+                 profile.title.as_ref().unwrap(),
+                 profiles.path().display());
+    }
+
+    let mut tone = Tone::default();
+    tone.waveform = opt.wave.unwrap_or(tone.waveform);
+    tone.pitch = opt.pitch.unwrap_or(tone.pitch);
+    tone.volume = opt.volume.unwrap_or(tone.volume);
+    tone.attack = opt.attack.map_or(tone.attack, |ms| ms / 1000.0);
+    tone.release = opt.release.map_or(tone.release, |ms| ms / 1000.0);
+    if tone.pitch <= 0.0 || !(0.0..=1.0).contains(&tone.volume) || tone.attack < 0.0 ||
+       tone.release < 0.0 {
+        fail("--pitch must be above 0, --volume from 0 to 1, and fade times at least 0");
+    }
 
     // SDL init
     let sdl_context = sdl2::init().unwrap();
@@ -72,30 +276,184 @@ pub fn main() {
     let audio_subsystem = sdl_context.audio().unwrap();
 
     let canvas = window.into_canvas().build().unwrap();
-    let event_pump = Arc::new(Mutex::new(sdl_context.event_pump().unwrap()));
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut display = SdlDisplay::new(canvas);
+    display.set_palette(&palette);
+    display.set_phosphor(opt.phosphor.unwrap_or(1));
 
-    // CPU init
+    // CPU init; the screen is pulled once per frame rather than pushed
     let mut c = Chip8::new(
-        SdlDisplay::new(canvas),
-        SdlInput::new(event_pump.clone()),
-        SdlBeeper::new(audio_subsystem),
+        (),
+        SdlInput::new(),
+        SdlBeeper::new(audio_subsystem, tone),
+        quirks,
     );
-    c.load(load_rom(opt.file).unwrap());
+    c.beeper_mut().set_muted(opt.mute);
+    if let Some(ref path) = opt.wav {
+        if let Err(e) = c.beeper_mut().record(path) {
+            fail(&format!("Could not create {}: {}", path, e));
+        }
+    }
+    if let Some(seed) = opt.seed {
+        c.seed_rng(seed);
+    }
+    if let Some(ref path) = opt.trace {
+        let trace = match File::create(path) {
+            Ok(f) => Trace::new(BufWriter::new(f)),
+            Err(e) => {
+                eprintln!("Could not create trace file {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        c.set_trace(Some(match opt.trace_range {
+            Some(range) => trace.range(range),
+            None => trace,
+        }));
+    }
+    // F5 saves the machine next to the ROM, F9 restores it
+    let state_path = format!("{}.state", opt.file);
+    let size = rom.len();
+    if let Err(e) = c.load(rom) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    println!("Loaded {} bytes into memory, SHA-1 {}", size, hash);
+
+    let mut gdb = opt.gdb.map(|port| match GdbStub::bind(port) {
+        Ok(stub) => {
+            println!("Listening for GDB on 127.0.0.1:{}", port);
+            stub
+        }
+        Err(e) => {
+            eprintln!("Could not listen on port {}: {}", port, e);
+            std::process::exit(1);
+        }
+    });
+
+    let mut recorder = opt.record.as_ref().map(|path| {
+        start_recording(path, display.palette()).unwrap_or_else(|| std::process::exit(1))
+    });
+
+    // Holding backspace steps back through the last ten seconds
+    let mut rewind = Rewind::new(10 * chip8::TIMER_HZ as usize / 2, 2);
+    let mut rewinding = false;
 
+    let frame_time = Duration::new(0, 1_000_000_000u32 / chip8::TIMER_HZ);
+    let mut next_frame = Instant::now();
     'running: loop {
-        {
-            let mut ugh = event_pump.lock().unwrap();
-            for event in ugh.poll_iter() {
-                match event {
-                    Event::Quit { .. } |
-                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'running,
-                    _ => {}
+        // Sleep to a fixed schedule, so that time spent emulating and
+        // drawing does not slow the frame rate down
+        next_frame += frame_time;
+        let now = Instant::now();
+        if next_frame > now {
+            ::std::thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'running,
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => rewinding = true,
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => rewinding = false,
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
+                    let saved = File::create(&state_path)
+                        .and_then(|mut f| f.write_all(&c.save_state()));
+                    if let Err(e) = saved {
+                        eprintln!("Could not save {}: {}", state_path, e);
+                    }
+                }
+                Event::KeyDown { keycode: Some(Keycode::F8), .. } => {
+                    let beeper = c.beeper_mut();
+                    let muted = !beeper.is_muted();
+                    beeper.set_muted(muted);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F10), .. } => {
+                    recorder = match recorder.take() {
+                        Some(r) => {
+                            stop_recording(Some(r));
+                            None
+                        }
+                        None => start_recording(&recording_path(&opt.file), display.palette()),
+                    };
+                }
+                Event::KeyDown { keycode: Some(Keycode::F9), .. } => {
+                    match load_rom(state_path.clone()) {
+                        Ok(state) => {
+                            match c.load_state(&state) {
+                                Ok(()) => rewind.clear(),
+                                Err(e) => eprintln!("{}", e),
+                            }
+                        }
+                        Err(e) => eprintln!("Could not load {}: {}", state_path, e),
+                    }
+                }
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    if let Some(k) = keymap.get(key) {
+                        c.set_key(k, true);
+                    }
+                }
+                Event::KeyUp { keycode: Some(key), .. } => {
+                    if let Some(k) = keymap.get(key) {
+                        c.set_key(k, false);
+                    }
                 }
+                _ => {}
             }
         }
-        if !c.cycle() {
-            break;
+        if rewinding {
+            rewind.rewind(&mut c);
+        } else {
+            let paused = gdb.as_ref().is_some_and(|g| g.is_paused());
+            if !paused {
+                rewind.record(&c);
+            }
+            let running = match gdb {
+                Some(ref mut stub) => {
+                    if let Err(e) = stub.poll(&mut c) {
+                        eprintln!("GDB connection failed: {}", e);
+                    }
+                    let running = stub.run(&mut c, ipf);
+                    if !paused {
+                        c.tick_timers();
+                    }
+                    running
+                }
+                None => c.run_frame(ipf).map(|frame| !frame.halted),
+            };
+            match running {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    // Flush the trace, which ends with the failing instruction
+                    c.set_trace(None);
+                    let _ = c.beeper_mut().stop_recording();
+                    stop_recording(recorder);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        let recorded = recorder.as_mut().map_or(Ok(()), |r| r.frame(c.framebuffer()));
+        if let Err(e) = recorded {
+            eprintln!("Could not record: {}", e);
+            recorder = None;
         }
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+
+        if !c.take_dirty().is_empty() || display.is_fading() {
+            if let Err(e) = display.present(c.framebuffer()) {
+                eprintln!("Display error: {}", e);
+                c.set_trace(None);
+                let _ = c.beeper_mut().stop_recording();
+                stop_recording(recorder);
+                std::process::exit(1);
+            }
+        }
+    }
+    stop_recording(recorder);
+    if let Err(e) = c.beeper_mut().stop_recording() {
+        fail(&format!("Could not write {}: {}", opt.wav.unwrap_or_default(), e));
     }
 }
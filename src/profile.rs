@@ -0,0 +1,469 @@
+//! Settings for particular ROMs, kept in a TOML file under the SHA-1 of each
+//! ROM:
+//!
+//! ```toml
+//! [0123456789abcdef0123456789abcdef01234567]
+//! title = "Pong"
+//! platform = "chip48"
+//! ipf = 15
+//! palette = ["000000", "FFCC00"]   # or a preset, such as "amber"
+//! keymap = ["1 = W, Up", "4 = S, Down"]
+//!
+//! [0123456789abcdef0123456789abcdef01234567.quirks]
+//! clip = false
+//! ```
+//!
+//! Only as much of TOML as this needs is understood: tables, strings,
+//! integers, booleans, and arrays of strings on one line
+
+extern crate chip8;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use chip8::Quirks;
+
+/// The settings for one ROM. Anything left out falls back to the defaults
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub title: Option<String>,
+
+    /// A quirks preset, as `--quirks` takes
+    pub platform: Option<String>,
+
+    pub ipf: Option<usize>,
+
+    /// Quirk flags that differ from the platform's preset, by field name
+    pub quirks: Vec<(String, bool)>,
+
+    /// Colors for the first planes, in place of the default palette's
+    pub palette: Vec<(u8, u8, u8)>,
+
+    /// Key mappings applied over the default layout
+    pub keymap: Vec<String>,
+}
+
+/// The SHA-1 of `rom` in lowercase hex, which profiles are kept under
+pub fn rom_sha1(rom: &[u8]) -> String {
+    chip8::sha1(rom).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A profile database and the file it came from. Saving rewrites the whole
+/// file, so comments in it are lost
+pub struct Profiles {
+    path: PathBuf,
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    /// `chip8/profiles.toml` in `$XDG_CONFIG_HOME` or `~/.config`
+    pub fn default_path() -> PathBuf {
+        let config = match (env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME")) {
+            (Some(dir), _) => PathBuf::from(dir),
+            (None, Some(home)) => Path::new(&home).join(".config"),
+            (None, None) => PathBuf::from("."),
+        };
+        config.join("chip8").join("profiles.toml")
+    }
+
+    /// Read the database at `path`, which is empty if there is no file yet
+    pub fn open(path: PathBuf) -> Result<Self, String> {
+        let mut text = String::new();
+        match File::open(&path).and_then(|mut f| f.read_to_string(&mut text)) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        }
+        let profiles = parse(&text).map_err(|e| format!("{}, {}", path.display(), e))?;
+        Ok(Profiles { path, profiles })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Profile> {
+        self.profiles.get(hash)
+    }
+
+    pub fn set(&mut self, hash: &str, profile: Profile) {
+        self.profiles.insert(hash.to_lowercase(), profile);
+    }
+
+    /// Write the database back to its file, creating its directory if needed
+    pub fn save(&self) -> Result<(), String> {
+        let saved = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => fs::create_dir_all(dir),
+            _ => Ok(()),
+        };
+        saved
+            .and_then(|_| File::create(&self.path))
+            .and_then(|mut f| f.write_all(write(&self.profiles).as_bytes()))
+            .map_err(|e| format!("Could not write {}: {}", self.path.display(), e))
+    }
+}
+
+/// Parse a color written as six hex digits, with or without a leading `#`
+pub fn parse_color(s: &str) -> Result<(u8, u8, u8), String> {
+    let hex = s.trim_start_matches('#');
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 => Ok(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+        _ => Err(format!("'{}' is not a color, expected RRGGBB in hex", s)),
+    }
+}
+
+/// The colors of `palette` up to the last that differs from the default,
+/// as profiles keep them
+pub fn changed_colors(palette: &[(u8, u8, u8); 16]) -> Vec<(u8, u8, u8)> {
+    let changed = palette.iter()
+        .zip(chip8::image::PALETTE.iter())
+        .rposition(|(a, b)| a != b)
+        .map_or(0, |i| i + 1);
+    palette[..changed].to_vec()
+}
+
+#[derive(Debug)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+fn parse(text: &str) -> Result<BTreeMap<String, Profile>, String> {
+    let mut profiles = BTreeMap::new();
+    // The ROM whose table is open, and whether it is the quirks table
+    let mut table: Option<(String, bool)> = None;
+
+    for (n, line) in text.lines().enumerate() {
+        let at_line = |e: String| format!("line {}: {}", n + 1, e);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let (name, rest) = match header.find(']') {
+                Some(end) => (header[..end].trim(), &header[end + 1..]),
+                None => return Err(at_line("expected ] after table name".to_string())),
+            };
+            end_of_line(rest).map_err(&at_line)?;
+            let (hash, quirks) = match name.find('.') {
+                Some(dot) if &name[dot..] == ".quirks" => (&name[..dot], true),
+                Some(_) => return Err(at_line(format!("unknown table '{}'", name))),
+                None => (name, false),
+            };
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(at_line(format!("'{}' is not a SHA-1 in hex", hash)));
+            }
+            let hash = hash.to_lowercase();
+            profiles.entry(hash.clone()).or_insert_with(Profile::default);
+            table = Some((hash, quirks));
+            continue;
+        }
+
+        let (key, rest) = match line.find('=') {
+            Some(eq) => (line[..eq].trim(), &line[eq + 1..]),
+            None => return Err(at_line("expected <key> = <value>".to_string())),
+        };
+        let (value, rest) = value(rest).map_err(&at_line)?;
+        end_of_line(rest).map_err(&at_line)?;
+        let (profile, quirks) = match table {
+            Some((ref hash, quirks)) => (profiles.get_mut(hash).unwrap(), quirks),
+            None => return Err(at_line(format!("'{}' is outside a ROM's table", key))),
+        };
+        if quirks {
+            set_quirk(profile, key, value).map_err(&at_line)?;
+        } else {
+            set(profile, key, value).map_err(&at_line)?;
+        }
+    }
+    Ok(profiles)
+}
+
+fn set(profile: &mut Profile, key: &str, value: Value) -> Result<(), String> {
+    match (key, value) {
+        ("title", Value::Str(s)) => profile.title = Some(s),
+        ("platform", Value::Str(s)) => {
+            s.parse::<Quirks>().map_err(|e| e.to_string())?;
+            profile.platform = Some(s);
+        }
+        ("ipf", Value::Int(n)) if n > 0 => profile.ipf = Some(n as usize),
+        ("ipf", Value::Int(_)) => return Err("ipf must be at least 1".to_string()),
+        ("palette", Value::List(colors)) => {
+            let max = chip8::image::PALETTE.len();
+            if colors.len() > max {
+                return Err(format!("a palette has at most {} colors", max));
+            }
+            profile.palette = colors.iter()
+                .map(|c| parse_color(c))
+                .collect::<Result<_, _>>()?;
+        }
+        ("palette", Value::Str(name)) => {
+            match chip8::image::palette(&name) {
+                Some(palette) => profile.palette = changed_colors(&palette),
+                None => return Err(format!("unknown palette '{}'", name)),
+            }
+        }
+        ("keymap", Value::List(mappings)) => profile.keymap = mappings,
+        ("title", _) | ("platform", _) | ("keymap", _) | ("palette", _) | ("ipf", _) => {
+            return Err(format!("wrong type of value for '{}'", key))
+        }
+        _ => return Err(format!("unknown setting '{}'", key)),
+    }
+    Ok(())
+}
+
+fn set_quirk(profile: &mut Profile, key: &str, value: Value) -> Result<(), String> {
+    let value = match value {
+        Value::Bool(b) => b,
+        _ => return Err(format!("quirk '{}' must be true or false", key)),
+    };
+    if !Quirks::default().set(key, value) {
+        return Err(format!("unknown quirk '{}'", key));
+    }
+    profile.quirks.retain(|(name, _)| name != key);
+    profile.quirks.push((key.to_string(), value));
+    Ok(())
+}
+
+/// Check nothing but a comment follows a value
+fn end_of_line(rest: &str) -> Result<(), String> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected '{}'", rest))
+    }
+}
+
+/// Parse the value at the start of `text`, returning it and what follows
+fn value(text: &str) -> Result<(Value, &str), String> {
+    let text = text.trim_start();
+    if text.starts_with('"') {
+        let (s, rest) = string(text)?;
+        return Ok((Value::Str(s), rest));
+    }
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::List(items), after));
+            }
+            if !rest.starts_with('"') {
+                return Err("arrays may only hold strings".to_string());
+            }
+            let (s, after) = string(rest)?;
+            items.push(s);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.starts_with(']') {
+                return Err("expected , or ] in array".to_string());
+            }
+        }
+    }
+
+    let end = text.find(|c: char| c.is_whitespace() || c == '#').unwrap_or(text.len());
+    let (word, rest) = text.split_at(end);
+    let value = match word {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => {
+            match word.replace('_', "").parse() {
+                Ok(n) => Value::Int(n),
+                Err(_) => return Err(format!("'{}' is not a value", word)),
+            }
+        }
+    };
+    Ok((value, rest))
+}
+
+/// Parse the basic string starting at `text`'s opening quote
+fn string(text: &str) -> Result<(String, &str), String> {
+    let mut s = String::new();
+    let mut chars = text[1..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((s, &text[i + 2..])),
+            '\\' => {
+                match chars.next() {
+                    Some((_, '"')) => s.push('"'),
+                    Some((_, '\\')) => s.push('\\'),
+                    Some((_, 'n')) => s.push('\n'),
+                    Some((_, 't')) => s.push('\t'),
+                    _ => return Err("unknown escape in string".to_string()),
+                }
+            }
+            c => s.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+fn quote(s: &str) -> String {
+    let escaped = s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+fn write(profiles: &BTreeMap<String, Profile>) -> String {
+    let mut out = String::new();
+    let list = |items: Vec<String>| {
+        let quoted: Vec<_> = items.iter().map(|s| quote(s)).collect();
+        format!("[{}]", quoted.join(", "))
+    };
+    for (hash, profile) in profiles {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("[{}]\n", hash));
+        if let Some(ref title) = profile.title {
+            out.push_str(&format!("title = {}\n", quote(title)));
+        }
+        if let Some(ref platform) = profile.platform {
+            out.push_str(&format!("platform = {}\n", quote(platform)));
+        }
+        if let Some(ipf) = profile.ipf {
+            out.push_str(&format!("ipf = {}\n", ipf));
+        }
+        if !profile.palette.is_empty() {
+            let colors = profile.palette
+                .iter()
+                .map(|&(r, g, b)| format!("{:02X}{:02X}{:02X}", r, g, b))
+                .collect();
+            out.push_str(&format!("palette = {}\n", list(colors)));
+        }
+        if !profile.keymap.is_empty() {
+            out.push_str(&format!("keymap = {}\n", list(profile.keymap.clone())));
+        }
+        if !profile.quirks.is_empty() {
+            out.push_str(&format!("\n[{}.quirks]\n", hash));
+            for &(ref name, value) in &profile.quirks {
+                out.push_str(&format!("{} = {}\n", name, value));
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn profile(text: &str) -> Result<Profile, String> {
+        parse(&format!("[{}]\n{}", HASH, text)).map(|mut p| p.remove(HASH).unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut profiles = BTreeMap::new();
+        profiles.insert(HASH.to_string(), Profile {
+            title: Some("\"Pong\" \\ 2\n\tby Paul".to_string()),
+            platform: Some("chip48".to_string()),
+            ipf: Some(15),
+            quirks: vec![("clip".to_string(), false), ("index_overflow".to_string(), true)],
+            palette: vec![(0, 0, 0), (0xFF, 0xCC, 0x00)],
+            keymap: vec!["1 = W, Up".to_string(), "4 = S, Down".to_string()],
+        });
+        profiles.insert("f".repeat(40), Profile {
+            ipf: Some(1000),
+            ..Profile::default()
+        });
+
+        let text = write(&profiles);
+        assert_eq!(
+            format!(
+                "[{}]\n\
+                 title = \"\\\"Pong\\\" \\\\ 2\\n\\tby Paul\"\n\
+                 platform = \"chip48\"\n\
+                 ipf = 15\n\
+                 palette = [\"000000\", \"FFCC00\"]\n\
+                 keymap = [\"1 = W, Up\", \"4 = S, Down\"]\n\
+                 \n\
+                 [{}.quirks]\n\
+                 clip = false\n\
+                 index_overflow = true\n\
+                 \n\
+                 [{}]\n\
+                 ipf = 1000\n",
+                HASH, HASH, "f".repeat(40)
+            ),
+            text
+        );
+        assert_eq!(profiles, parse(&text).unwrap());
+    }
+
+    #[test]
+    fn parse_text() {
+        let text = "# Settings\n\
+                    [0123456789ABCDEF0123456789ABCDEF01234567]  # Pong\n\
+                    title = \"a # b\"  # not part of the title\n\
+                    palette = \"amber\"\n\
+                    ipf = 1_000\n\
+                    keymap = [ \"1 = W\" , ]\n\
+                    \n\
+                    [0123456789abcdef0123456789abcdef01234567.quirks]\n\
+                    clip = true\n\
+                    vf_reset = false\n\
+                    clip = false\n";
+        let profiles = parse(text).unwrap();
+        assert_eq!(vec![HASH], profiles.keys().collect::<Vec<_>>());
+        let p = &profiles[HASH];
+        assert_eq!(Some("a # b".to_string()), p.title);
+        assert_eq!(changed_colors(&chip8::image::palette("amber").unwrap()), p.palette);
+        assert_eq!(Some(1000), p.ipf);
+        assert_eq!(vec!["1 = W".to_string()], p.keymap);
+        // A quirk set twice keeps the last value, in the order last set
+        assert_eq!(
+            vec![("vf_reset".to_string(), false), ("clip".to_string(), false)],
+            p.quirks
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let err = |text: &str| profile(text).unwrap_err();
+        assert_eq!("line 2: unknown setting 'speed'", err("speed = 10"));
+        assert_eq!("line 2: wrong type of value for 'ipf'", err("ipf = \"fast\""));
+        assert_eq!("line 2: ipf must be at least 1", err("ipf = 0"));
+        assert_eq!("line 2: unterminated string", err("title = \"Pong"));
+        assert_eq!("line 2: unknown escape in string", err("title = \"\\x\""));
+        assert_eq!("line 2: unexpected '\"b\"'", err("title = \"a\" \"b\""));
+        assert_eq!("line 2: 'fast' is not a value", err("ipf = fast"));
+        assert_eq!("line 2: arrays may only hold strings", err("keymap = [1]"));
+        assert_eq!("line 2: expected , or ] in array", err("keymap = [\"a\" \"b\"]"));
+        assert_eq!("line 2: expected <key> = <value>", err("title"));
+        assert_eq!("line 2: unknown palette 'mauve'", err("palette = \"mauve\""));
+        assert_eq!(
+            "line 2: 'red' is not a color, expected RRGGBB in hex",
+            err("palette = [\"red\"]")
+        );
+        assert_eq!(
+            "line 2: Unknown quirks preset 'nes', expected one of vip, chip48, schip, xochip",
+            err("platform = \"nes\"")
+        );
+
+        let quirks = |text: &str| profile(&format!("[{}.quirks]\n{}", HASH, text)).unwrap_err();
+        assert_eq!("line 3: unknown quirk 'wrap'", quirks("wrap = true"));
+        assert_eq!("line 3: quirk 'clip' must be true or false", quirks("clip = 1"));
+
+        assert_eq!(
+            Err("line 1: 'title' is outside a ROM's table".to_string()),
+            parse("title = \"Pong\"")
+        );
+        assert_eq!(Err("line 1: 'abc' is not a SHA-1 in hex".to_string()), parse("[abc]"));
+        assert_eq!(
+            Err(format!("line 1: unknown table '{}.keys'", HASH)),
+            parse(&format!("[{}.keys]", HASH))
+        );
+        assert_eq!(Err("line 1: expected ] after table name".to_string()), parse("[abc"));
+    }
+}